@@ -0,0 +1,217 @@
+//! Reading of the configuration file supplying defaults for the command-line
+//! options.  Only the subset of TOML needed for such defaults is understood:
+//! top-level `key = value` pairs, each value being a string, integer,
+//! boolean, or a single-line array of those.
+
+use std::io::{Error, ErrorKind, Result};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A value assigned to a key
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Array(vals) => {
+                let vals: Vec<String> =
+                    vals.iter().map(|v| v.to_string()).collect();
+                write!(f, "[{}]", vals.join(", "))
+            }
+        }
+    }
+}
+
+fn skip_space(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+}
+
+/// Parse the string following an opening `quote`, with escapes only in
+/// basic (`"`) strings
+fn parse_str(chars: &mut Peekable<Chars>, quote: char) -> Option<String> {
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            c if c == quote => return Some(out),
+            '\\' if quote == '"' => out.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            }),
+            c => out.push(c),
+        }
+    }
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Option<Value> {
+    skip_space(chars);
+    match chars.next()? {
+        q @ ('"' | '\'') => parse_str(chars, q).map(Value::Str),
+        '[' => {
+            let mut vals = Vec::new();
+            loop {
+                skip_space(chars);
+                if chars.next_if_eq(&']').is_some() {
+                    return Some(Value::Array(vals));
+                }
+                vals.push(parse_value(chars)?);
+                skip_space(chars);
+                match chars.next()? {
+                    ',' => {}
+                    ']' => return Some(Value::Array(vals)),
+                    _ => return None,
+                }
+            }
+        }
+        c => {
+            let mut word = c.to_string();
+            while let Some(c) =
+                chars.next_if(|c| c.is_alphanumeric() || *c == '_')
+            {
+                word.push(c);
+            }
+            match word.as_str() {
+                "true" => Some(Value::Bool(true)),
+                "false" => Some(Value::Bool(false)),
+                _ => word.replace('_', "").parse().ok().map(Value::Int),
+            }
+        }
+    }
+}
+
+/// Parse the `key = value` pair on `line`, if it holds one
+fn parse_line(line: &str) -> Option<(String, Value)> {
+    let (key, rest) = line.split_once('=')?;
+    let key = key.trim();
+    let key = match key.strip_prefix('"').and_then(|k| k.strip_suffix('"')) {
+        Some(key) => key,
+        None if !key.is_empty()
+            && key.chars().all(|c| c.is_alphanumeric() || "-_".contains(c)) =>
+        {
+            key
+        }
+        None => return None,
+    };
+    let mut chars = rest.chars().peekable();
+    let value = parse_value(&mut chars)?;
+    // Only a comment may follow
+    skip_space(&mut chars);
+    match chars.next() {
+        None | Some('#') => Some((key.to_string(), value)),
+        Some(_) => None,
+    }
+}
+
+/// Parse the configuration in `text` into its keys and values, in order.
+/// Blank lines and those starting with `#` are ignored.
+pub fn parse(text: &str) -> Result<Vec<(String, Value)>> {
+    let mut out: Vec<(String, Value)> = Vec::new();
+    for (num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |msg: &str| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("line {}: {}", num + 1, msg),
+            )
+        };
+        let (key, value) = parse_line(line)
+            .ok_or_else(|| invalid("expected <key> = <value>"))?;
+        if out.iter().any(|(k, _)| *k == key) {
+            return Err(invalid(&format!("{} is set more than once", key)));
+        }
+        out.push((key, value));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str) -> Value {
+        let mut pairs = parse(text).unwrap();
+        assert_eq!(pairs.len(), 1);
+        pairs.pop().unwrap().1
+    }
+
+    fn error(text: &str) -> String {
+        parse(text).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn strings_and_escapes() {
+        let str = |s: &str| Value::Str(s.to_string());
+        assert_eq!(value(r#"k = "a b""#), str("a b"));
+        assert_eq!(value(r#"k = "a\"b\\c\td\ne""#), str("a\"b\\c\td\ne"));
+        // Literal strings take backslashes as they are
+        assert_eq!(value(r"k = 'C:\Build'"), str(r"C:\Build"));
+        assert_eq!(value(r#""k" = 'a # b'"#), str("a # b"));
+        assert_eq!(error(r#"k = "a\qb""#), "line 1: expected <key> = <value>");
+        assert_eq!(error(r#"k = "open"#), "line 1: expected <key> = <value>");
+    }
+
+    #[test]
+    fn numbers_booleans_and_arrays() {
+        assert_eq!(value("jobs = 1_000"), Value::Int(1000));
+        assert_eq!(value("offset = -16"), Value::Int(-16));
+        assert_eq!(value("data = false"), Value::Bool(false));
+        assert_eq!(value("module = []"), Value::Array(Vec::new()));
+        assert_eq!(
+            value(r#"module = [ "Foo*", 'Bar',]"#),
+            Value::Array(vec![
+                Value::Str("Foo*".to_string()),
+                Value::Str("Bar".to_string()),
+            ])
+        );
+        assert_eq!(
+            value("k = [1, [true]]"),
+            Value::Array(vec![
+                Value::Int(1),
+                Value::Array(vec![Value::Bool(true)]),
+            ])
+        );
+        assert_eq!(error("k = [1 2]"), "line 1: expected <key> = <value>");
+        assert_eq!(error("k = yes"), "line 1: expected <key> = <value>");
+    }
+
+    #[test]
+    fn comments_and_blank_lines() {
+        let text = "# defaults\n\n  format = \"gdb\"  # trailing\ndata=true\n";
+        assert_eq!(
+            parse(text).unwrap(),
+            [
+                ("format".to_string(), Value::Str("gdb".to_string())),
+                ("data".to_string(), Value::Bool(true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_lines() {
+        assert_eq!(error("\ndata\n"), "line 2: expected <key> = <value>");
+        assert_eq!(error("= true"), "line 1: expected <key> = <value>");
+        assert_eq!(error("a b = true"), "line 1: expected <key> = <value>");
+        assert_eq!(
+            error("data = true false"),
+            "line 1: expected <key> = <value>"
+        );
+        assert_eq!(error("[section]"), "line 1: expected <key> = <value>");
+        assert_eq!(
+            error("data = true\n\"data\" = false"),
+            "line 2: data is set more than once"
+        );
+    }
+}
@@ -0,0 +1,658 @@
+//! Parsing of the EDK2 debug output, tracking where modules are loaded.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::LazyLock;
+use std::thread::JoinHandle;
+
+use flate2::read::MultiGzDecoder;
+use regex::{Captures, Regex};
+
+use crate::Radix;
+
+/// A module loaded by the firmware
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    /// Base name of the image, without the `.efi` extension
+    pub base: String,
+    /// Entry point address, if logged
+    pub entry: Option<u64>,
+    /// Name to give the module's symbols, if other than `base`
+    pub name: Option<String>,
+    /// Whether the image was logged as having started
+    pub started: bool,
+    /// The file GUID of the image, if logged
+    pub guid: Option<String>,
+}
+
+impl Module {
+    /// The name under which the module's symbols are emitted
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.base)
+    }
+}
+
+/// Loaded modules, keyed by load address
+pub type ModMap = BTreeMap<u64, Module>;
+
+/// Parse an address, which is hex if prefixed by 0x, and otherwise in
+/// `radix`
+fn parse_addr(s: &str, radix: Radix) -> Option<u64> {
+    match (s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")), radix) {
+        (Some(digits), _) => u64::from_str_radix(digits, 16).ok(),
+        (None, Radix::Hex) => u64::from_str_radix(s, 16).ok(),
+        (None, Radix::Dec) => s.parse().ok(),
+    }
+}
+
+/// Remove the ANSI control sequences (such as color changes) which some
+/// consoles wrap around their messages.
+fn strip_ansi(line: &str) -> Cow<'_, str> {
+    if !line.contains('\x1b') {
+        return Cow::Borrowed(line);
+    }
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        // A CSI sequence is ESC '[', any parameter and intermediate bytes,
+        // then a single final byte in the range '@' to '~'.
+        if chars.next_if_eq(&'[').is_some() {
+            while chars.next_if(|c| ('\x20'..'\x40').contains(c)).is_some() {}
+            chars.next_if(|c| ('@'..='~').contains(c));
+        }
+    }
+    Cow::Owned(out)
+}
+
+// Captured output may prefix each line with a timestamp or port tag, so the
+// messages are matched by their leading keyword, wherever it falls.  Some
+// DEBUG messages print the addresses as hex without the 0x prefix, which the
+// surrounding words leave no doubt about.  Matching `\s` also covers the
+// trailing `\r` of logs captured with CRLF line endings.
+
+/// A pattern capturing the `addr` following a word, as in "at <addr>"
+macro_rules! addr_re {
+    () => {
+        r"\s+(?P<addr>(?:0[xX])?[[:xdigit:]]+)(?:\s+|$)"
+    };
+}
+
+// Follow along as modules are loaded, whatever the phase calls them:
+// "Loading PEIM at 0x<address> EntryPoint=0x<entry> <file>.efi"
+// "Loading driver at 0x<address> EntryPoint=0x<entry> <file>.efi"
+// "DxeCore: Loading ... at 0x<address> ... <file>.efi"
+// Older firmware omits the EntryPoint, so the file and entry point are each
+// sought anywhere in the rest of the line, rather than at fixed positions.
+// "Loading driver at 0x<address> <file>.efi"
+static LOAD_RE: LazyLock<Regex> = LazyLock::new(|| {
+    let re =
+        concat!(r"(?:^|\s)Loading\s(?:.*?\s)?at", addr_re!(), "(?P<rest>.*)");
+    Regex::new(re).unwrap()
+});
+static ENTRY_RE: LazyLock<Regex> = LazyLock::new(|| {
+    let re = r"(?:^|\s)EntryPoint=(?P<entry>(?:0[xX])?[[:xdigit:]]+)(?:\s|$)";
+    Regex::new(re).unwrap()
+});
+// Some logs also name the file GUID of the image, anywhere in the line:
+// "Loading driver 5AE3F37E-4EAE-41AE-8240-35465B5E81EB at 0x<address> ..."
+static GUID_RE: LazyLock<Regex> = LazyLock::new(|| {
+    let re = concat!(
+        r"(?:^|\s)(?P<guid>[[:xdigit:]]{8}-(?:[[:xdigit:]]{4}-){3}",
+        r"[[:xdigit:]]{12})(?:\s|$)"
+    );
+    Regex::new(re).unwrap()
+});
+static FILE_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|\s)(?P<file>\S+)\.efi(?:\s|$)").unwrap()
+});
+
+// Drivers which are disconnected and unloaded no longer occupy their address
+// range:
+// "Unloading <something> at 0x<address> ..."
+static UNLOAD_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(r"(?:^|\s)Unloading\s(?:.*?\s)?at", addr_re!())).unwrap()
+});
+
+// Images which go on to run are reported as started:
+// "Image at <addr> started"
+static START_RE: LazyLock<Regex> = LazyLock::new(|| {
+    let re = concat!(r"(?:^|\s)Image\s+at", addr_re!(), r"started(?:\s|$)");
+    Regex::new(re).unwrap()
+});
+
+// Handle cases where an image load/start fails:
+// "Error: Image at <addr> start failed: ..."
+static FAIL_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(r"(?:^|\s)Error:\s+Image\s+at", addr_re!())).unwrap()
+});
+
+/// A change to the loaded modules described by a line of the log
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A module was loaded at the address
+    Load(u64, Module),
+    /// The module at the address was unloaded, or failed to start
+    Unload(u64),
+    /// The module at the address was started
+    Start(u64),
+}
+
+/// Parse the module load, unload or start, if any, described by `line`, in
+/// which addresses without a 0x prefix are in `radix`.
+pub fn parse_event(line: &str, radix: Radix) -> Option<Event> {
+    let line = strip_ansi(line);
+    let addr = |caps: &Captures| parse_addr(&caps["addr"], radix);
+
+    if let Some(caps) = LOAD_RE.captures(&line) {
+        let rest = &caps["rest"];
+        let file = FILE_RE.captures(rest)?;
+        let entry = ENTRY_RE
+            .captures(rest)
+            .and_then(|caps| parse_addr(&caps["entry"], radix));
+        // Some logging configurations print the full path of the image, of
+        // which only the file name identifies the module
+        let base = file["file"].rsplit(['/', '\\']).next().unwrap();
+        let base = base.to_string();
+        let guid = GUID_RE.captures(&line).map(|c| c["guid"].to_string());
+        let module = Module { base, entry, name: None, started: false, guid };
+        Some(Event::Load(addr(&caps)?, module))
+    } else if let Some(caps) = UNLOAD_RE.captures(&line) {
+        Some(Event::Unload(addr(&caps)?))
+    } else if let Some(caps) = FAIL_RE.captures(&line) {
+        Some(Event::Unload(addr(&caps)?))
+    } else if let Some(caps) = START_RE.captures(&line) {
+        Some(Event::Start(addr(&caps)?))
+    } else {
+        None
+    }
+}
+
+/// Record `module` as loaded at `addr` in `map`, replacing whatever was
+/// loaded there.  A module reloaded elsewhere is no longer at its previous
+/// address, so that mapping is dropped, and its address returned.
+pub fn insert(map: &mut ModMap, addr: u64, module: Module) -> Option<u64> {
+    let prev = map
+        .iter()
+        .find(|(prev, m)| **prev != addr && m.base == module.base)
+        .map(|(prev, _)| *prev);
+    if let Some(prev) = prev {
+        map.remove(&prev);
+    }
+    map.insert(addr, module);
+    prev
+}
+
+/// A module found loaded at a new address, as reported by [`apply`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Moved {
+    pub base: String,
+    /// The address it was previously loaded at
+    pub from: u64,
+    pub to: u64,
+}
+
+/// Update `map` with `event`.  A module loaded again elsewhere is taken to
+/// have moved, as with [`insert`], and reported, unless `keep_both` is set to
+/// keep each address as a distinct image.
+pub fn apply(map: &mut ModMap, event: Event, keep_both: bool) -> Option<Moved> {
+    match event {
+        Event::Load(addr, module) if keep_both => {
+            map.insert(addr, module);
+        }
+        Event::Load(addr, module) => {
+            let base = module.base.clone();
+            let from = insert(map, addr, module)?;
+            return Some(Moved { base, from, to: addr });
+        }
+        Event::Unload(addr) => {
+            map.remove(&addr);
+        }
+        Event::Start(addr) => {
+            if let Some(module) = map.get_mut(&addr) {
+                module.started = true;
+            }
+        }
+    }
+    None
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The output of the `zstd` command decompressing its input, which fails at
+/// the end if the command, or copying the input to it, did.  The command is
+/// waited for at the end, or killed if dropped before then.
+struct Unzstd {
+    child: Child,
+    out: ChildStdout,
+    copy: Option<JoinHandle<Result<u64>>>,
+    done: bool,
+}
+
+impl Unzstd {
+    /// Decompress `input`, copied to the command from a thread of its own
+    fn spawn(mut input: Box<dyn BufRead + Send>) -> Result<Self> {
+        let mut child = Command::new("zstd")
+            .args(["-dc", "-q"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                Error::new(e.kind(), format!("unable to run zstd: {}", e))
+            })?;
+        let mut stdin = child.stdin.take().unwrap();
+        let copy =
+            std::thread::spawn(move || std::io::copy(&mut input, &mut stdin));
+        let out = child.stdout.take().unwrap();
+        Ok(Unzstd { child, out, copy: Some(copy), done: false })
+    }
+
+    /// Wait for the command, once its output is exhausted
+    fn finish(&mut self) -> Result<()> {
+        self.done = true;
+        let status = self.child.wait()?;
+        if !status.success() {
+            let msg = format!(
+                "zstd failed to decompress the debug output ({})",
+                status
+            );
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+        // Having read all its input, the command leaves the copy finished
+        match self.copy.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => Err(Error::new(
+                e.kind(),
+                format!("unable to read the debug output: {}", e),
+            )),
+            Some(Err(_)) => Err(Error::other("zstd input copy panicked")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Read for Unzstd {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.out.read(buf)?;
+        if len == 0 && !buf.is_empty() && !self.done {
+            self.finish()?;
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for Unzstd {
+    fn drop(&mut self) {
+        // Left unread, the command may be blocked writing its output.  The
+        // copy thread is not joined, as it may be blocked reading stdin; it
+        // ends at its next write to the closed pipe.
+        if !self.done {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Open the debug output at `path` for reading, with `-` meaning stdin.
+/// Compressed output is transparently decompressed, if either its extension
+/// or its leading magic bytes indicate gzip or zstd.  There being no zstd
+/// decoder among the dependencies, the latter is left to the `zstd` command.
+pub fn open(path: &Path) -> Result<Box<dyn BufRead>> {
+    let mut reader: Box<dyn BufRead + Send> = if path.as_os_str() == "-" {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+    let ext = path.extension().and_then(|e| e.to_str());
+    if ext == Some("gz") || reader.fill_buf()?.starts_with(GZIP_MAGIC) {
+        reader = Box::new(BufReader::new(MultiGzDecoder::new(reader)));
+    } else if ext == Some("zst") || reader.fill_buf()?.starts_with(ZSTD_MAGIC) {
+        reader = Box::new(BufReader::new(Unzstd::spawn(reader)?));
+    }
+    Ok(reader)
+}
+
+/// The lines of `reader`, without their `\n`.  Bytes which are not UTF-8, as
+/// raw serial captures are apt to hold, are replaced rather than failing.
+pub fn lines<R: BufRead>(reader: R) -> impl Iterator<Item = Result<String>> {
+    reader.split(b'\n').map(|line| {
+        line.map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    })
+}
+
+/// Update `map` with the modules loaded in the debug output in `reader`, in
+/// which addresses without a 0x prefix are in `radix`, returning those which
+/// moved (see [`apply`]).
+pub fn parse_log<R: BufRead>(
+    map: &mut ModMap,
+    reader: R,
+    radix: Radix,
+    keep_both: bool,
+) -> Result<Vec<Moved>> {
+    let mut moved = Vec::new();
+    for line in lines(reader) {
+        if let Some(event) = parse_event(&line?, radix) {
+            moved.extend(apply(map, event, keep_both));
+        }
+    }
+    Ok(moved)
+}
+
+/// Read a map of the modules loaded from `reader`, in which each line holds
+/// a module name and its (hex) load address, separated by whitespace, as an
+/// alternative to finding them in a log.  Blank lines and those starting
+/// with `#` are ignored.  The modules listed are taken to have started.
+pub fn parse_addrmap<R: BufRead>(reader: R) -> Result<ModMap> {
+    let mut map = ModMap::new();
+    for (num, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let entry = match fields.as_slice() {
+            [base, addr] => parse_addr(addr, Radix::Hex).map(|a| (base, a)),
+            _ => None,
+        };
+        let (base, addr) = entry.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("line {}: expected <module> <hex address>", num + 1),
+            )
+        })?;
+        let module = Module {
+            base: base.to_string(),
+            entry: None,
+            name: None,
+            started: true,
+            guid: None,
+        };
+        insert(&mut map, addr, module);
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(text: &str) -> ModMap {
+        let mut map = ModMap::new();
+        parse_log(&mut map, text.as_bytes(), Radix::Hex, false).unwrap();
+        map
+    }
+
+    #[test]
+    fn lines_replace_invalid_utf8() {
+        let log: &[u8] = b"\xffgarbage\nLoading driver at 0x1000 Foo.efi\n";
+        let lines: Vec<String> = lines(log).map(|l| l.unwrap()).collect();
+        assert_eq!(lines[0], "\u{fffd}garbage");
+        let mut map = ModMap::new();
+        parse_log(&mut map, log, Radix::Hex, false).unwrap();
+        assert_eq!(map[&0x1000].base, "Foo");
+    }
+
+    #[test]
+    fn unload_drops_module() {
+        let map = log(concat!(
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 Foo.efi\n",
+            "Loading driver at 0x7E000000 EntryPoint=0x7E000240 Bar.efi\n",
+            "Unloading driver at 0x7F000000\n",
+        ));
+        assert_eq!(map.keys().collect::<Vec<_>>(), [&0x7e00_0000]);
+    }
+
+    #[test]
+    fn prefixed_lines_match_clean_ones() {
+        let clean = concat!(
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 Foo.efi\n",
+            "Loading driver at 0x7E000000 EntryPoint=0x7E000240 Bar.efi\n",
+            "Image at 0x7F000000 started\n",
+        );
+        let prefixed = concat!(
+            "[12.345] Loading driver at 0x7F000000 EntryPoint=0x7F000240 ",
+            "Foo.efi\n",
+            "COM1: Loading driver at 0x7E000000 EntryPoint=0x7E000240 ",
+            "Bar.efi\n",
+            "[12.400] Image at 0x7F000000 started\n",
+        );
+        assert_eq!(log(prefixed), log(clean));
+        assert_eq!(log(clean).len(), 2);
+    }
+
+    #[test]
+    fn colorized_load_line() {
+        let map = log(concat!(
+            "\x1b[1;32mLoading driver at 0x7F000000\x1b[0m ",
+            "EntryPoint=\x1b[33m0x7F000240\x1b[0m Foo.efi\x1b[0m\n",
+        ));
+        assert_eq!(map[&0x7f00_0000].base, "Foo");
+        assert_eq!(map[&0x7f00_0000].entry, Some(0x7f00_0240));
+    }
+
+    #[test]
+    fn prefixed_and_bare_hex_addresses() {
+        let prefixed =
+            log("Loading driver at 0x7F000000 EntryPoint=0x7F000240 Foo.efi\n");
+        let bare =
+            log("Loading driver at 7F000000 EntryPoint=7F000240 Foo.efi\n");
+        assert_eq!(bare, prefixed);
+        assert_eq!(bare[&0x7f00_0000].entry, Some(0x7f00_0240));
+        // Without the surrounding words, numbers are left alone
+        assert!(log("Allocated 1000 pages at 7F000000\n").is_empty());
+    }
+
+    #[test]
+    fn decimal_addresses() {
+        let text = concat!(
+            "Loading driver at 2130706432 EntryPoint=2130707008 Foo.efi\n",
+            "Loading driver at 0x7E000000 EntryPoint=0x7E000240 Bar.efi\n",
+            "Image at 2130706432 started\n",
+        );
+        let mut map = ModMap::new();
+        parse_log(&mut map, text.as_bytes(), Radix::Dec, false).unwrap();
+        // Those prefixed by 0x are hex regardless
+        assert_eq!(
+            map.keys().collect::<Vec<_>>(),
+            [&0x7e00_0000, &0x7f00_0000]
+        );
+        let foo = &map[&0x7f00_0000];
+        assert_eq!((foo.entry, foo.started), (Some(0x7f00_0240), true));
+        assert_eq!(map[&0x7e00_0000].entry, Some(0x7e00_0240));
+        // Which the default reads as hex
+        assert!(log(text).contains_key(&0x21_3070_6432));
+    }
+
+    #[test]
+    fn load_wordings() {
+        for line in [
+            "Loading PEIM at 0x7F000000 EntryPoint=0x7F000240 Foo.efi",
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 Foo.efi",
+            "DxeCore: Loading image at 0x7F000000 into memory, Foo.efi",
+        ] {
+            let map = log(line);
+            assert_eq!(
+                map.keys().collect::<Vec<_>>(),
+                [&0x7f00_0000],
+                "{}",
+                line
+            );
+            assert_eq!(map[&0x7f00_0000].base, "Foo");
+        }
+    }
+
+    #[test]
+    fn with_and_without_entry_point() {
+        let text = concat!(
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 Foo.efi\n",
+            "Loading driver at 0x7E000000 Bar.efi\n",
+        );
+        let mut map = ModMap::new();
+        parse_log(&mut map, text.as_bytes(), Radix::Hex, false).unwrap();
+        assert_eq!(map.len(), 2);
+        let (foo, bar) = (&map[&0x7f00_0000], &map[&0x7e00_0000]);
+        assert_eq!((foo.base.as_str(), foo.entry), ("Foo", Some(0x7f00_0240)));
+        assert_eq!((bar.base.as_str(), bar.entry), ("Bar", None));
+    }
+
+    #[test]
+    fn path_qualified_names() {
+        let map = log(concat!(
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 ",
+            "/work/Build/OvmfX64/DEBUG_GCC5/X64/Foo.efi\n",
+            "Loading driver at 0x7E000000 EntryPoint=0x7E000240 ",
+            "c:\\edk2\\Build\\OvmfX64\\DEBUG_VS2019\\X64\\Bar.efi\n",
+        ));
+        assert_eq!(map[&0x7f00_0000].base, "Foo");
+        assert_eq!(map[&0x7e00_0000].base, "Bar");
+        assert_eq!(map[&0x7e00_0000].entry, Some(0x7e00_0240));
+    }
+
+    #[test]
+    fn guid_on_load_line() {
+        let map = log(concat!(
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 ",
+            "1A1E4886-9517-440E-9FDE-3BE44CEE2136 Foo.efi\n",
+            "Loading driver at 0x7E000000 EntryPoint=0x7E000240 Bar.efi\n",
+        ));
+        let foo = &map[&0x7f00_0000];
+        assert_eq!(foo.base, "Foo");
+        assert_eq!(
+            foo.guid.as_deref(),
+            Some("1A1E4886-9517-440E-9FDE-3BE44CEE2136")
+        );
+        assert_eq!(map[&0x7e00_0000].guid, None);
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let line = "Loading driver at 0x7F000000 Foo.efi\r\n";
+        match parse_event(line, Radix::Hex) {
+            Some(Event::Load(0x7f00_0000, module)) => {
+                assert_eq!(module.base, "Foo")
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+        let map = log(concat!(
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 Foo.efi\r\n",
+            "Image at 0x7F000000 started\r\n",
+        ));
+        assert_eq!(map[&0x7f00_0000].entry, Some(0x7f00_0240));
+        assert!(map[&0x7f00_0000].started);
+    }
+
+    #[test]
+    fn odd_spacing() {
+        let map = log(concat!(
+            "Loading  driver   at\t0x7F000000   EntryPoint=0x7F000240",
+            "\tFoo.efi\n",
+            "Loading driver at 0x7E000000 Bar.efi  \n",
+            "Loading driver at 0x7D000000 Baz.efi\n",
+            "Image   at  0x7F000000   started\n",
+            "Unloading\tdriver  at  0x7E000000\n",
+            "Error:   Image  at 0x7D000000 start failed: Unsupported\n",
+        ));
+        assert_eq!(map.keys().collect::<Vec<_>>(), [&0x7f00_0000]);
+        let foo = &map[&0x7f00_0000];
+        assert_eq!((foo.entry, foo.started), (Some(0x7f00_0240), true));
+    }
+
+    #[test]
+    fn moved_modules_reported() {
+        let text = concat!(
+            "Loading driver at 0x7F000000 Foo.efi\n",
+            "Loading driver at 0x7E000000 Foo.efi\n",
+        );
+        let mut map = ModMap::new();
+        let moved =
+            parse_log(&mut map, text.as_bytes(), Radix::Hex, false).unwrap();
+        let base = "Foo".to_string();
+        assert_eq!(moved, [Moved { base, from: 0x7f00_0000, to: 0x7e00_0000 }]);
+        assert_eq!(map.keys().collect::<Vec<_>>(), [&0x7e00_0000]);
+
+        let mut map = ModMap::new();
+        let moved =
+            parse_log(&mut map, text.as_bytes(), Radix::Hex, true).unwrap();
+        assert!(moved.is_empty());
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn reload_after_unload() {
+        let text = concat!(
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 Foo.efi\n",
+            "Unloading driver at 0x7F000000\n",
+            "Loading driver at 0x7E000000 EntryPoint=0x7E000240 Foo.efi\n",
+        );
+        let mut map = ModMap::new();
+        let moved =
+            parse_log(&mut map, text.as_bytes(), Radix::Hex, false).unwrap();
+        // Having been unloaded, it did not move, but the last load wins
+        assert!(moved.is_empty());
+        assert_eq!(map.keys().collect::<Vec<_>>(), [&0x7e00_0000]);
+        assert_eq!(map[&0x7e00_0000].entry, Some(0x7e00_0240));
+    }
+
+    #[test]
+    fn repeated_load_kept_once() {
+        let line =
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 Foo.efi\n";
+        let mut map = ModMap::new();
+        let text = [line, line, line].concat();
+        let moved =
+            parse_log(&mut map, text.as_bytes(), Radix::Hex, false).unwrap();
+        assert!(moved.is_empty());
+        assert_eq!(map, log(line));
+    }
+
+    #[test]
+    #[ignore = "needs the zstd command; run with --ignored where installed"]
+    fn zstd_matches_plain_text() {
+        let dir = crate::testelf::TempDir::new();
+        let text = concat!(
+            "Loading driver at 0x7F000000 EntryPoint=0x7F000240 Foo.efi\n",
+            "Loading driver at 0x7E000000 EntryPoint=0x7E000240 Bar.efi\n",
+            "Image at 0x7F000000 started\n",
+        );
+        let plain = dir.join("debug.log");
+        std::fs::write(&plain, text).unwrap();
+        // Compressed by the command, which decompression needs in any case
+        let zst = dir.join("debug.log.zst");
+        let status = Command::new("zstd")
+            .arg("-q")
+            .arg(&plain)
+            .arg("-o")
+            .arg(&zst)
+            .status()
+            .expect("unable to run zstd");
+        assert!(status.success());
+        // Detected by its magic bytes, as well as its extension
+        let unnamed = dir.join("debug.bin");
+        std::fs::copy(&zst, &unnamed).unwrap();
+        let read = |path: &Path| {
+            let mut map = ModMap::new();
+            let reader = open(path).unwrap();
+            parse_log(&mut map, reader, Radix::Hex, false).unwrap();
+            map
+        };
+        assert_eq!(read(&zst), log(text));
+        assert_eq!(read(&unnamed), log(text));
+        assert_eq!(read(&plain), log(text));
+        // A truncated file fails, rather than ending early
+        let data = std::fs::read(&zst).unwrap();
+        let short = dir.join("short.log.zst");
+        std::fs::write(&short, &data[..data.len() - 4]).unwrap();
+        let mut map = ModMap::new();
+        let reader = open(&short).unwrap();
+        let err = parse_log(&mut map, reader, Radix::Hex, false).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,452 @@
+//! Just enough DWARF to map function addresses to their source lines: the
+//! line number programs of `.debug_line`, which are run to build a table of
+//! the addresses at which each line of source begins.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+use goblin::container::{Container, Ctx, Endian};
+use goblin::elf::compression_header::{CompressionHeader, ELFCOMPRESS_ZLIB};
+use goblin::elf::section_header::SHF_COMPRESSED;
+use goblin::elf::Elf;
+
+// Line number program opcodes
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_NEGATE_STMT: u8 = 6;
+const DW_LNS_CONST_ADD_PC: u8 = 8;
+const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const DW_LNE_DEFINE_FILE: u8 = 3;
+
+// DWARF 5 entry formats for the directory and file name tables
+const DW_LNCT_PATH: u64 = 1;
+const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+
+/// The source line beginning at each address, with `None` marking the end
+/// of a sequence of instructions
+pub(crate) struct LineTable {
+    rows: BTreeMap<u64, Option<(usize, u64)>>,
+    files: Vec<String>,
+}
+
+impl LineTable {
+    /// The `file:line` of the code at `addr`, an address as linked
+    pub(crate) fn lookup(&self, addr: u64) -> Option<String> {
+        let (_, row) = self.rows.range(..=addr).next_back()?;
+        let (file, line) = (*row)?;
+        Some(format!("{}:{}", self.files[file], line))
+    }
+}
+
+/// The contents of the section `name`, decompressed if need be
+fn section<'a>(elf: &Elf, data: &'a [u8], name: &str) -> Option<Cow<'a, [u8]>> {
+    let hdr = elf.section_headers.iter().find(|hdr| {
+        elf.shdr_strtab.get(hdr.sh_name).and_then(|r| r.ok()) == Some(name)
+    })?;
+    let start = usize::try_from(hdr.sh_offset).ok()?;
+    let bytes = data.get(start..start.checked_add(hdr.sh_size as usize)?)?;
+    if hdr.sh_flags & u64::from(SHF_COMPRESSED) == 0 {
+        return Some(Cow::Borrowed(bytes));
+    }
+    let container = if elf.is_64 { Container::Big } else { Container::Little };
+    let ctx = Ctx::new(container, Endian::from(elf.little_endian));
+    let chdr = CompressionHeader::parse(bytes, 0, ctx).ok()?;
+    if chdr.ch_type != ELFCOMPRESS_ZLIB {
+        return None;
+    }
+    let mut out = Vec::with_capacity(chdr.ch_size as usize);
+    ZlibDecoder::new(&bytes[CompressionHeader::size(ctx)..])
+        .read_to_end(&mut out)
+        .ok()?;
+    Some(Cow::Owned(out))
+}
+
+/// A cursor over DWARF data
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    little: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let out = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(out)
+    }
+
+    fn uint(&mut self, len: usize) -> Option<u64> {
+        let bytes = self.bytes(len)?;
+        let fold = |acc: u64, b: &u8| acc << 8 | u64::from(*b);
+        Some(match self.little {
+            true => bytes.iter().rev().fold(0, fold),
+            false => bytes.iter().fold(0, fold),
+        })
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn uleb(&mut self) -> Option<u64> {
+        let (mut val, mut shift) = (0u64, 0);
+        loop {
+            let b = self.u8()?;
+            if shift < 64 {
+                val |= u64::from(b & 0x7f) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                return Some(val);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Option<i64> {
+        let (mut val, mut shift) = (0i64, 0);
+        loop {
+            let b = self.u8()?;
+            if shift < 64 {
+                val |= i64::from(b & 0x7f) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                if shift < 64 && b & 0x40 != 0 {
+                    val |= -1 << shift;
+                }
+                return Some(val);
+            }
+        }
+    }
+
+    fn cstr(&mut self) -> Option<&'a str> {
+        let len = self.data.get(self.pos..)?.iter().position(|b| *b == 0)?;
+        let s = std::str::from_utf8(self.bytes(len)?).ok()?;
+        self.pos += 1;
+        Some(s)
+    }
+}
+
+/// The NUL-terminated string at `off` in `strs`
+fn str_at(strs: &[u8], off: u64) -> Option<&str> {
+    let mut reader =
+        Reader { data: strs, pos: usize::try_from(off).ok()?, little: true };
+    reader.cstr()
+}
+
+/// An entry of a DWARF 5 directory or file name table
+#[derive(Default)]
+struct Entry<'a> {
+    path: Option<&'a str>,
+    dir: u64,
+}
+
+/// Read a DWARF 5 directory or file name table, with entries described by
+/// the pairs of content type and form which precede it
+fn read_entries<'a>(
+    rd: &mut Reader<'a>,
+    offset_size: usize,
+    strs: (&'a [u8], &'a [u8]),
+) -> Option<Vec<Entry<'a>>> {
+    let nformats = rd.u8()?;
+    let mut formats = Vec::new();
+    for _ in 0..nformats {
+        formats.push((rd.uleb()?, rd.uleb()?));
+    }
+    let count = rd.uleb()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let mut entry = Entry::default();
+        for (content, form) in formats.iter() {
+            let (mut path, mut val) = (None, 0);
+            match *form {
+                DW_FORM_STRING => path = Some(rd.cstr()?),
+                DW_FORM_LINE_STRP => {
+                    path = Some(str_at(strs.1, rd.uint(offset_size)?)?)
+                }
+                DW_FORM_STRP => {
+                    path = Some(str_at(strs.0, rd.uint(offset_size)?)?)
+                }
+                DW_FORM_UDATA => val = rd.uleb()?,
+                DW_FORM_DATA1 => val = rd.uint(1)?,
+                DW_FORM_DATA2 => val = rd.uint(2)?,
+                DW_FORM_DATA4 => val = rd.uint(4)?,
+                DW_FORM_DATA8 => val = rd.uint(8)?,
+                DW_FORM_DATA16 => {
+                    rd.bytes(16)?;
+                }
+                DW_FORM_BLOCK => {
+                    let len = rd.uleb()?;
+                    rd.bytes(usize::try_from(len).ok()?)?;
+                }
+                _ => return None,
+            }
+            match *content {
+                DW_LNCT_PATH => entry.path = path,
+                DW_LNCT_DIRECTORY_INDEX => entry.dir = val,
+                _ => {}
+            }
+        }
+        entries.push(entry);
+    }
+    Some(entries)
+}
+
+/// Join `name` to the directory `dir`, unless it is the compilation
+/// directory (index 0), which would only lengthen every name alike
+fn file_name(dirs: &[&str], dir: u64, name: &str) -> String {
+    let dir = usize::try_from(dir).ok().filter(|dir| *dir != 0);
+    match dir.and_then(|dir| dirs.get(dir)) {
+        Some(dir) if !name.starts_with('/') && !dir.is_empty() => {
+            format!("{}/{}", dir, name)
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// Run the line number program of the unit held by `rd`, following its
+/// length, adding its rows to `table`
+fn read_unit<'a>(
+    rd: &mut Reader<'a>,
+    offset_size: usize,
+    strs: (&'a [u8], &'a [u8]),
+    table: &mut LineTable,
+) -> Option<()> {
+    let version = rd.uint(2)?;
+    if !(2..=5).contains(&version) {
+        return None;
+    }
+    let mut addr_size = None;
+    if version >= 5 {
+        addr_size = Some(usize::from(rd.u8()?));
+        rd.u8()?;
+    }
+    let header_len = usize::try_from(rd.uint(offset_size)?).ok()?;
+    let prog_start = rd.pos.checked_add(header_len)?;
+    let min_inst = u64::from(rd.u8()?);
+    if version >= 4 {
+        rd.u8()?;
+    }
+    let default_is_stmt = rd.u8()? != 0;
+    let line_base = i64::from(rd.u8()? as i8);
+    let line_range = rd.u8()?;
+    let opcode_base = rd.u8()?;
+    if line_range == 0 || opcode_base == 0 {
+        return None;
+    }
+    let opcode_lens = rd.bytes(usize::from(opcode_base - 1))?;
+
+    // File numbers index this unit's names, which are appended to the table
+    // as a whole.  Before DWARF 5, they count from 1, with directory 0 (and
+    // file 0, in DWARF 5) standing for the compilation unit itself.
+    let first = table.files.len();
+    let mut dirs: Vec<&str> = Vec::new();
+    let base = if version >= 5 {
+        let entries = read_entries(rd, offset_size, strs)?;
+        dirs.extend(entries.iter().map(|e| e.path.unwrap_or("")));
+        for entry in read_entries(rd, offset_size, strs)? {
+            let name = file_name(&dirs, entry.dir, entry.path.unwrap_or(""));
+            table.files.push(name);
+        }
+        first
+    } else {
+        dirs.push("");
+        while let Some(dir) = rd.cstr().filter(|d| !d.is_empty()) {
+            dirs.push(dir);
+        }
+        loop {
+            let name = rd.cstr()?;
+            if name.is_empty() {
+                break;
+            }
+            let dir = rd.uleb()?;
+            rd.uleb()?;
+            rd.uleb()?;
+            table.files.push(file_name(&dirs, dir, name));
+        }
+        first.wrapping_sub(1)
+    };
+    let file_index = |file: u64, nfiles: usize| {
+        let idx = base.wrapping_add(usize::try_from(file).ok()?);
+        (first..nfiles).contains(&idx).then_some(idx)
+    };
+
+    rd.pos = prog_start;
+    let (mut addr, mut file, mut line) = (0u64, 1u64, 1u64);
+    let mut is_stmt = default_is_stmt;
+    while rd.pos < rd.data.len() {
+        let op = rd.u8()?;
+        let mut emit = false;
+        if op >= opcode_base {
+            let adj = op - opcode_base;
+            addr = addr.wrapping_add(u64::from(adj / line_range) * min_inst);
+            let delta = line_base + i64::from(adj % line_range);
+            line = line.wrapping_add(delta as u64);
+            emit = true;
+        } else if op == 0 {
+            let len = usize::try_from(rd.uleb()?).ok()?;
+            let end = rd.pos.checked_add(len)?;
+            match rd.u8()? {
+                DW_LNE_END_SEQUENCE => {
+                    table.rows.entry(addr).or_insert(None);
+                    addr = 0;
+                    file = 1;
+                    line = 1;
+                    is_stmt = default_is_stmt;
+                }
+                DW_LNE_SET_ADDRESS => {
+                    let size = addr_size.or_else(|| len.checked_sub(1))?;
+                    addr = rd.uint(size)?;
+                }
+                DW_LNE_DEFINE_FILE => {
+                    let name = rd.cstr()?;
+                    let dir = rd.uleb()?;
+                    table.files.push(file_name(&dirs, dir, name));
+                }
+                _ => {}
+            }
+            rd.pos = end;
+        } else {
+            match op {
+                DW_LNS_COPY => emit = true,
+                DW_LNS_ADVANCE_PC => {
+                    addr = addr.wrapping_add(rd.uleb()?.wrapping_mul(min_inst))
+                }
+                DW_LNS_ADVANCE_LINE => {
+                    line = line.wrapping_add(rd.sleb()? as u64)
+                }
+                DW_LNS_SET_FILE => file = rd.uleb()?,
+                DW_LNS_CONST_ADD_PC => {
+                    let adj = (255 - opcode_base) / line_range;
+                    addr = addr.wrapping_add(u64::from(adj) * min_inst);
+                }
+                DW_LNS_FIXED_ADVANCE_PC => {
+                    addr = addr.wrapping_add(rd.uint(2)?)
+                }
+                DW_LNS_NEGATE_STMT => is_stmt = !is_stmt,
+                _ => {
+                    for _ in 0..opcode_lens[usize::from(op - 1)] {
+                        rd.uleb()?;
+                    }
+                }
+            }
+        }
+        if emit && is_stmt {
+            if let Some(idx) = file_index(file, table.files.len()) {
+                // The first row at an address is the start of its line,
+                // which may also be where another sequence ended
+                let row = table.rows.entry(addr).or_insert(None);
+                if row.is_none() {
+                    *row = Some((idx, line));
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+/// Build the line table of `elf`, given its contents in `data`.  Objects
+/// without line information have none, while units with line information
+/// beyond what is understood here are passed over, leaving only their own
+/// functions without source lines.
+pub(crate) fn line_table(elf: &Elf, data: &[u8]) -> Option<LineTable> {
+    let lines = section(elf, data, ".debug_line")?;
+    let strs = section(elf, data, ".debug_str").unwrap_or_default();
+    let line_strs = section(elf, data, ".debug_line_str").unwrap_or_default();
+    let mut table = LineTable { rows: BTreeMap::new(), files: Vec::new() };
+    let little = elf.little_endian;
+    let mut rd = Reader { data: &lines, pos: 0, little };
+    while rd.pos < lines.len() {
+        let (offset_size, unit_len) = match rd.uint(4) {
+            Some(0xffff_ffff) => (8, rd.uint(8)),
+            len => (4, len),
+        };
+        // Without a length to skip to, nothing further can be found
+        let unit_end = match unit_len
+            .and_then(|len| usize::try_from(len).ok())
+            .and_then(|len| rd.pos.checked_add(len))
+            .filter(|end| *end <= lines.len())
+        {
+            Some(end) => end,
+            None => break,
+        };
+        // A unit which cannot be read keeps only the rows added before its
+        // failure, and the next is read regardless
+        let mut unit = Reader { data: &lines[..unit_end], pos: rd.pos, little };
+        read_unit(&mut unit, offset_size, (&strs, &line_strs), &mut table);
+        rd.pos = unit_end;
+    }
+    Some(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testelf::{testdata, TempDir};
+
+    // Where foo (of a.c) and bar (of b.c) are linked in each fixture
+    const FOO: u64 = 0x210;
+    const BAR: u64 = 0x214;
+
+    /// The source lines of foo and bar in the object `data`
+    fn lookup(data: &[u8]) -> (Option<String>, Option<String>) {
+        let elf = Elf::parse(data).unwrap();
+        let table = line_table(&elf, data).unwrap();
+        (table.lookup(FOO), table.lookup(BAR))
+    }
+
+    #[test]
+    fn dwarf_versions_and_compression() {
+        let want = (Some("a.c:3".to_string()), Some("b.c:5".to_string()));
+        for name in ["Line4.debug", "Line5.debug", "LineZ.debug"] {
+            let data = std::fs::read(testdata("dwarf").join(name)).unwrap();
+            assert_eq!(lookup(&data), want, "{}", name);
+        }
+    }
+
+    #[test]
+    fn bad_unit_skipped() {
+        let path = testdata("dwarf/Line4.debug");
+        let mut data = std::fs::read(&path).unwrap();
+        let elf = Elf::parse(&data).unwrap();
+        let off = elf
+            .section_headers
+            .iter()
+            .find(|hdr| {
+                elf.shdr_strtab.get(hdr.sh_name).and_then(|r| r.ok())
+                    == Some(".debug_line")
+            })
+            .unwrap()
+            .sh_offset as usize;
+        // An unknown version in the first unit, of foo, leaves that of bar
+        data[off + 4] = 9;
+        assert_eq!(lookup(&data), (None, Some("b.c:5".to_string())));
+
+        // As read by process_file
+        let dir = TempDir::new();
+        let path = dir.join("Line.debug");
+        std::fs::write(&path, &data).unwrap();
+        let opts = crate::Options { source: true, ..Default::default() };
+        let obj = crate::process_file("Line", &path, 0x1000, &opts).unwrap();
+        let sources: Vec<Option<&str>> =
+            obj.text().syms.iter().map(|r| r.source.as_deref()).collect();
+        assert_eq!(sources, [None, Some("b.c:5")]);
+    }
+}
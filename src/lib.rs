@@ -0,0 +1,2077 @@
+//! Symbol extraction from EDK2 `.debug` ELF objects, producing mdb `::nmadd`
+//! commands for the modules loaded by the firmware.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+extern crate cpp_demangle;
+extern crate flate2;
+extern crate glob;
+extern crate goblin;
+extern crate memmap;
+extern crate regex;
+extern crate rustc_demangle;
+extern crate serde_json;
+
+use goblin::container::{Container, Ctx, Endian};
+use goblin::elf::compression_header::CompressionHeader;
+use goblin::elf::note::NT_GNU_BUILD_ID;
+use goblin::elf::section_header::SHN_ABS;
+use goblin::elf::section_header::{
+    SectionHeader, SHF_ALLOC, SHF_COMPRESSED, SHF_EXECINSTR, SHT_FINI_ARRAY,
+    SHT_INIT_ARRAY, SHT_NOBITS, SHT_PREINIT_ARRAY,
+};
+use goblin::elf::sym::{STB_LOCAL, STB_WEAK, STT_FILE, STT_SECTION};
+
+pub mod config;
+pub mod debuglog;
+mod dwarf;
+pub mod objdir;
+pub mod pdb;
+pub mod pe;
+#[cfg(test)]
+mod testelf;
+
+/// A symbol resolved from an object file, relocated to the module load address
+pub struct SymRes {
+    pub addr: u64,
+    pub name: String,
+    pub size: u64,
+    pub is_func: bool,
+    /// Further names for the same address, when collecting aliases
+    pub aliases: Vec<String>,
+    /// The `file:line` at which a function begins, when requested
+    pub source: Option<String>,
+}
+
+impl SymRes {
+    /// Record `name` as an alias, unless it is already known
+    fn add_alias(&mut self, name: String) {
+        if name != self.name && !self.aliases.contains(&name) {
+            self.aliases.push(name);
+        }
+    }
+
+    /// Take on the names of `other`, a symbol at the same address, as aliases
+    fn absorb(&mut self, other: SymRes) {
+        self.add_alias(other.name);
+        for name in other.aliases {
+            self.add_alias(name);
+        }
+    }
+}
+
+/// An allocated section of an object file, relocated to the module load
+/// address
+pub struct Section {
+    pub name: String,
+    pub start: u64,
+    pub end: u64,
+    /// Symbols within the section, ordered by address
+    pub syms: Vec<SymRes>,
+    /// Synthetic markers, such as those added by [`ObjSyms::add_bounds`],
+    /// which are output with their own sizes, regardless of any filters
+    pub marks: Vec<SymRes>,
+}
+
+/// Symbols found in an object file, grouped by section
+pub struct ObjSyms {
+    /// The allocated sections, ordered by start address.  Only those selected
+    /// by the [`Options`] have their symbols populated.
+    pub sections: Vec<Section>,
+    /// Index of the `.text` section (or the code section standing in for it)
+    /// within `sections`
+    pub text: usize,
+    /// Symbols with absolute values (`SHN_ABS`), ordered by address, if
+    /// requested by the [`Options`].  Being outside of any section, they are
+    /// not sized by their neighbours, but output with their own sizes.
+    pub abs: Vec<SymRes>,
+    /// Problems with the object which did not prevent reading it, for the
+    /// caller to report
+    pub warnings: Vec<String>,
+}
+
+impl ObjSyms {
+    /// The `.text` section
+    pub fn text(&self) -> &Section {
+        &self.sections[self.text]
+    }
+
+    /// The number of (functions, objects) among the symbols
+    pub fn counts(&self) -> (usize, usize) {
+        let syms = self.sections.iter().flat_map(|s| s.syms.iter());
+        let funcs = syms.filter(|res| res.is_func).count();
+        let total: usize = self.sections.iter().map(|s| s.syms.len()).sum();
+        (funcs, total - funcs + self.abs.len())
+    }
+
+    /// The fraction of `.text` covered by its functions, by their sizes as
+    /// recorded (overlaps aside).  There is nothing to judge by for an empty
+    /// `.text`, or one whose functions are all unsized, as in PE images, so
+    /// `None` is returned for those.
+    pub fn text_coverage(&self) -> Option<f64> {
+        let text = self.text();
+        let funcs: Vec<&SymRes> =
+            text.syms.iter().filter(|res| res.is_func).collect();
+        if text.end == text.start
+            || (!funcs.is_empty() && funcs.iter().all(|res| res.size == 0))
+        {
+            return None;
+        }
+        let covered: u64 = funcs
+            .iter()
+            .map(|res| res.size.min(text.end.saturating_sub(res.addr)))
+            .sum();
+        let len = text.end - text.start;
+        Some((covered.min(len) as f64) / (len as f64))
+    }
+
+    /// Keep only the `n` largest of the symbols to be output with `fopts`,
+    /// by their sizes as output, breaking ties by address.  The sizes of
+    /// those kept are fixed first, so that they are unchanged by the rest
+    /// being dropped.
+    pub fn limit(&mut self, n: usize, fopts: &FmtOpts) {
+        // (size, section, address) of each symbol output
+        let mut ranked: Vec<(u64, usize, u64)> = Vec::new();
+        for (idx, sect) in self.sections.iter().enumerate() {
+            let mut seen = HashSet::new();
+            for (addr, size, _, _) in fopts.sized(sect) {
+                // Aliases repeat an address, and markers have none of their own
+                let is_sym =
+                    sect.syms.binary_search_by_key(&addr, |r| r.addr).is_ok();
+                if is_sym && seen.insert(addr) {
+                    ranked.push((size, idx, addr));
+                }
+            }
+        }
+        ranked.sort_by_key(|(size, idx, addr)| {
+            (std::cmp::Reverse(*size), *addr, *idx)
+        });
+        let kept: HashMap<(usize, u64), u64> = ranked
+            .into_iter()
+            .take(n)
+            .map(|(size, idx, addr)| ((idx, addr), size))
+            .collect();
+        for (idx, sect) in self.sections.iter_mut().enumerate() {
+            sect.syms.retain_mut(|res| match kept.get(&(idx, res.addr)) {
+                Some(size) => {
+                    res.size = *size;
+                    true
+                }
+                None => false,
+            });
+        }
+    }
+
+    /// Add a synthetic symbol, placing it in the section containing its
+    /// address.  Nothing is added if the address lies outside every section
+    /// or is already occupied by a symbol; the return value indicates whether
+    /// the symbol was added.
+    pub fn add_synthetic(&mut self, res: SymRes) -> bool {
+        if self
+            .sections
+            .iter()
+            .any(|s| s.syms.iter().any(|r| r.addr == res.addr))
+        {
+            return false;
+        }
+        let sect = match self
+            .sections
+            .iter_mut()
+            .find(|s| s.start <= res.addr && res.addr < s.end)
+        {
+            Some(sect) => sect,
+            None => return false,
+        };
+        let pos = sect.syms.partition_point(|r| r.addr < res.addr);
+        sect.syms.insert(pos, res);
+        true
+    }
+
+    /// Add a marker, placing it in the section containing its address, or
+    /// failing that in `.text`.  Unlike a synthetic symbol, it is output
+    /// whether or not the address is occupied.
+    pub fn add_mark(&mut self, res: SymRes) {
+        let idx = self
+            .sections
+            .iter()
+            .position(|s| s.start <= res.addr && res.addr < s.end)
+            .unwrap_or(self.text);
+        self.sections[idx].marks.push(res);
+    }
+
+    /// Add `<base>_<section>_start` and `<base>_<section>_end` markers, of
+    /// size zero, at the bounds of `.text` and any other section holding
+    /// symbols, to bracket the range of the module.  A bound already
+    /// occupied by a symbol is left alone.
+    pub fn add_bounds(&mut self, base: &str) {
+        let occupied: HashSet<u64> = self
+            .sections
+            .iter()
+            .flat_map(|s| s.syms.iter().map(|res| res.addr))
+            .collect();
+        for (idx, sect) in self.sections.iter_mut().enumerate() {
+            if idx != self.text && sect.syms.is_empty() {
+                continue;
+            }
+            let name = sect.name.trim_start_matches('.').replace('.', "_");
+            for (addr, which) in [(sect.start, "start"), (sect.end, "end")] {
+                if occupied.contains(&addr) {
+                    continue;
+                }
+                sect.marks.push(SymRes {
+                    addr,
+                    name: format!("{}_{}_{}", base, name, which),
+                    size: 0,
+                    is_func: false,
+                    aliases: Vec::new(),
+                    source: None,
+                });
+            }
+        }
+    }
+}
+
+/// Options controlling which symbols are extracted from an object file
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Include symbols from all allocated sections (`.data`, `.bss`,
+    /// `.rodata`, ...) rather than just `.text`
+    pub data: bool,
+    /// Include local symbols beyond functions, such as static variables, with
+    /// repeated names made unique by a numeric suffix
+    pub locals: bool,
+    /// Demangle Rust and C++ symbol names
+    pub demangle: bool,
+    /// Keep every name at an address, rather than just the preferred one
+    pub aliases: bool,
+    /// Level of diagnostic detail to log to stderr: 1 for a summary of each
+    /// object, 2 to add the section headers
+    pub verbose: u8,
+    /// Reject objects which appear not to match the loaded image, rather than
+    /// only warning about them
+    pub strict: bool,
+    /// Keep the `@VERSION` or `@@VERSION` suffix of versioned symbol names
+    pub keep_version: bool,
+    /// Include symbols with absolute values (`SHN_ABS`), such as hardware
+    /// register bases
+    pub abs: bool,
+    /// Treat absolute values as offsets from the load address, rather than
+    /// as addresses in their own right
+    pub abs_relocate: bool,
+    /// Look up the source line at which each function begins, in the DWARF
+    /// line information
+    pub source: bool,
+    /// Treat untyped global symbols in executable sections as functions, as
+    /// is typical of those defined in assembly
+    pub asm_as_func: bool,
+    /// Skip weak symbols, which may have been overridden by a definition
+    /// elsewhere
+    pub no_weak: bool,
+    /// Include the constructor and destructor arrays (`.init_array`,
+    /// `.fini_array`, `.ctors`, ...), naming their entries after the
+    /// functions they point to
+    pub ctors: bool,
+}
+
+/// Radix in which sizes are written, or addresses read from the log
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Radix {
+    Hex,
+    /// Decimal, written with mdb's `0t` prefix since it otherwise assumes hex
+    Dec,
+}
+
+impl std::str::FromStr for Radix {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(Radix::Hex),
+            "dec" => Ok(Radix::Dec),
+            _ => Err("unknown radix"),
+        }
+    }
+}
+
+/// Kinds of symbol to output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    All,
+    Func,
+    Obj,
+}
+
+impl std::str::FromStr for Kind {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Kind::All),
+            "func" => Ok(Kind::Func),
+            "obj" => Ok(Kind::Obj),
+            _ => Err("unknown kind"),
+        }
+    }
+}
+
+/// Options controlling how symbols are named and sized in the output
+#[derive(Clone, Debug)]
+pub struct FmtOpts {
+    /// Separator between the module and symbol names
+    pub delim: char,
+    /// Omit symbols smaller than this, once zero sizes have been stretched
+    pub min_size: u64,
+    /// Include the name of the containing section (`<base>.text.<name>`)
+    pub qualify_section: bool,
+    /// Radix of the `::nmadd` size argument
+    pub radix: Radix,
+    /// Give the extent of each symbol with `::nmadd -e <end>`, rather than
+    /// `-s <size>`
+    pub use_end: bool,
+    /// Prepended to each module name (as `<prefix>_<base>`), to distinguish
+    /// the symbols of different builds
+    pub prefix: Option<String>,
+    /// Which kinds of symbol to output.  The sizes of those output are still
+    /// computed with respect to all of the symbols.
+    pub kind: Kind,
+    /// Remove a single leading `_` from symbol names, as prepended to C
+    /// names by some toolchains
+    pub strip_underscore: bool,
+    /// If set, only output symbols whose names match one of these
+    pub only: Option<Vec<glob::Pattern>>,
+    /// Omit symbols whose names match any of these, even if also matched by
+    /// `only`
+    pub exclude: Vec<glob::Pattern>,
+    /// Output zero sizes as they are, rather than stretching them to the
+    /// next symbol
+    pub no_stretch: bool,
+}
+
+impl Default for FmtOpts {
+    fn default() -> Self {
+        // While '`' would be the expected delimiter between object and
+        // function name, it (currently) confuses name resolution in mdb-bhyve
+        // since there are effectively no objects.  Use '.' instead, so the
+        // private symbols can be referred to directly.
+        FmtOpts {
+            delim: '.',
+            min_size: 0,
+            qualify_section: false,
+            radix: Radix::Hex,
+            use_end: false,
+            kind: Kind::All,
+            prefix: None,
+            strip_underscore: false,
+            only: None,
+            exclude: Vec::new(),
+            no_stretch: false,
+        }
+    }
+}
+
+impl FmtOpts {
+    /// Whether `delim` can separate names within a quoted mdb argument
+    pub fn valid_delim(delim: char) -> bool {
+        !(delim.is_whitespace() || delim == '"' || delim == '\'')
+    }
+
+    /// Whether `prefix` can be prepended to module names, given the delimiter
+    pub fn valid_prefix(&self, prefix: &str) -> bool {
+        !prefix.is_empty()
+            && !prefix.contains(|c: char| {
+                c.is_whitespace() || matches!(c, '"' | '`' | '\\' | '\'')
+            })
+            && !prefix.contains(self.delim)
+    }
+
+    /// The name under which the symbols of module `base` are output
+    fn module_name(&self, base: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{}_{}", prefix, base),
+            None => base.to_string(),
+        }
+    }
+
+    /// The name of `name` in `sect` qualified by the module `base`.
+    /// Characters which would break the quoted mdb string, or be mistaken for
+    /// the delimiter, are replaced by `_`.
+    fn qual_name(&self, base: &str, sect: &Section, name: &str) -> String {
+        let clean = |s: &str| s.replace(['"', '`', '\\', self.delim], "_");
+        let base = &self.module_name(base);
+        match self.qualify_section {
+            true => format!(
+                "{}{}{}{}{}",
+                clean(base),
+                self.delim,
+                clean(sect.name.trim_start_matches('.')),
+                self.delim,
+                clean(name)
+            ),
+            false => format!("{}{}{}", clean(base), self.delim, clean(name)),
+        }
+    }
+
+    /// Format `size` as an mdb numeric argument
+    fn size_arg(&self, size: u64) -> String {
+        match self.radix {
+            Radix::Hex => format!("{:x}", size),
+            Radix::Dec => format!("0t{}", size),
+        }
+    }
+
+    /// The qualified name of each symbol of `sect`, in the module named
+    /// `base`, in the order the formatting functions output them
+    pub fn output_names(&self, sect: &Section, base: &str) -> Vec<String> {
+        self.sized(sect)
+            .map(|(_, _, _, name)| self.qual_name(base, sect, name))
+            .collect()
+    }
+
+    /// Whether the symbol `name` passes the `only` and `exclude` patterns
+    fn wanted(&self, name: &str) -> bool {
+        let only = match &self.only {
+            Some(pats) => pats.iter().any(|pat| pat.matches(name)),
+            None => true,
+        };
+        only && !self.exclude.iter().any(|pat| pat.matches(name))
+    }
+
+    /// The symbols of `sect` which are to be output, as sized by
+    /// [`compute_sizes`] (unless `no_stretch` is set), along with each of
+    /// their names as output, followed by its markers
+    fn sized<'a>(
+        &'a self,
+        sect: &'a Section,
+    ) -> impl Iterator<Item = (u64, u64, &'a SymRes, &'a str)> + 'a {
+        let (min_size, kind) = (self.min_size, self.kind);
+        let strip = self.strip_underscore;
+        let marks =
+            sect.marks.iter().map(|res| (res.addr, res.size, res, &*res.name));
+        let sizes = match self.no_stretch {
+            true => {
+                sect.syms.iter().map(|res| (res.addr, res.size, res)).collect()
+            }
+            false => compute_sizes(&sect.syms, sect.end),
+        };
+        sizes
+            .into_iter()
+            .filter(move |(_, size, res)| {
+                *size >= min_size
+                    && match kind {
+                        Kind::All => true,
+                        Kind::Func => res.is_func,
+                        Kind::Obj => !res.is_func,
+                    }
+            })
+            .flat_map(move |(addr, size, res)| {
+                std::iter::once(&res.name)
+                    .chain(res.aliases.iter())
+                    .map(move |name| match name.strip_prefix('_') {
+                        Some(rest) if strip && !rest.is_empty() => rest,
+                        _ => name.as_str(),
+                    })
+                    .filter(move |name| self.wanted(name))
+                    .map(move |name| (addr, size, res, name))
+            })
+            .chain(marks)
+    }
+}
+
+/// Compute the effective size of each entry in `syms` (sorted by address),
+/// yielding `(addr, size, sym)` tuples in the same order.  Zero-size entries
+/// which cannot be stretched, because they lie beyond `addr_end`, are skipped
+/// (and noted among the [`ObjSyms::warnings`] by [`process_file`]).
+pub fn compute_sizes(
+    syms: &[SymRes],
+    addr_end: u64,
+) -> Vec<(u64, u64, &SymRes)> {
+    let mut out = Vec::with_capacity(syms.len());
+    let mut iter = syms.iter().peekable();
+    while let Some(res) = iter.next() {
+        let size = match res.size {
+            0 => {
+                // For any entries which lack a proper size, stretch it out
+                // until it hits the next entry (or the end of the section).
+                let next = match iter.peek() {
+                    Some(next) => next.addr.min(addr_end),
+                    None => addr_end,
+                };
+                // Only possible for an entry past the section end, which a
+                // mismatched object file can produce.
+                match next.checked_sub(res.addr) {
+                    Some(sz) => sz,
+                    None => continue,
+                }
+            }
+            sz => sz,
+        };
+        out.push((res.addr, size, res));
+    }
+    out
+}
+
+/// Format the symbols of `sect` as mdb `::nmadd` commands for the module named
+/// `base`.  The source line of a symbol, if known, follows as a comment.
+pub fn format_nmadd(
+    sect: &Section,
+    base: &str,
+    fopts: &FmtOpts,
+) -> Vec<String> {
+    fopts
+        .sized(sect)
+        .map(|(addr, size, res, name)| {
+            // The end, being an address, is always written in hex
+            let extent = match fopts.use_end {
+                true => format!("-e {:x}", addr.saturating_add(size)),
+                false => format!("-s {}", fopts.size_arg(size)),
+            };
+            let cmd = format!(
+                "{:x}::nmadd -{} {} \"{}\"",
+                addr,
+                if res.is_func { "f" } else { "o" },
+                extent,
+                fopts.qual_name(base, sect, name)
+            );
+            match &res.source {
+                Some(source) => format!("{} // {}", cmd, source),
+                None => cmd,
+            }
+        })
+        .collect()
+}
+
+/// Describe the symbols of `sect` in the module named `base` as JSON objects,
+/// with the same sizes used by [`format_nmadd`].
+pub fn format_json(
+    sect: &Section,
+    base: &str,
+    fopts: &FmtOpts,
+) -> Vec<serde_json::Value> {
+    fopts
+        .sized(sect)
+        .map(|(addr, size, res, name)| {
+            serde_json::json!({
+                "module": fopts.module_name(base),
+                "addr": format!("0x{:x}", addr),
+                "size": format!("0x{:x}", size),
+                "name": name,
+                "kind": if res.is_func { "func" } else { "obj" },
+            })
+        })
+        .collect()
+}
+
+/// Format the symbols of `sect` in the module named `base` as rows of a
+/// table for reading: address, size, kind (`F` or `O`), module and name,
+/// with the same sizes used by [`format_nmadd`].
+pub fn format_table(
+    sect: &Section,
+    base: &str,
+    fopts: &FmtOpts,
+) -> Vec<String> {
+    fopts
+        .sized(sect)
+        .map(|(addr, size, res, name)| {
+            format!(
+                "{:016x} {:>8x} {} {:<16} {}",
+                addr,
+                size,
+                if res.is_func { "F" } else { "O" },
+                fopts.module_name(base),
+                name
+            )
+        })
+        .collect()
+}
+
+/// Format a GDB `add-symbol-file` command loading the object at `path` with
+/// the section addresses in `obj`.
+pub fn format_gdb(obj: &ObjSyms, path: &Path) -> String {
+    let mut cmd =
+        format!("add-symbol-file {} 0x{:x}", path.display(), obj.text().start);
+    for (idx, sect) in obj.sections.iter().enumerate() {
+        if idx != obj.text && !sect.name.is_empty() {
+            cmd.push_str(&format!(" -s {} 0x{:x}", sect.name, sect.start));
+        }
+    }
+    cmd
+}
+
+/// Format the symbols of `sect` as mdb `::nmdel` commands, removing those
+/// which [`format_nmadd`] would add for the module named `base`.
+pub fn format_nmdel(
+    sect: &Section,
+    base: &str,
+    fopts: &FmtOpts,
+) -> Vec<String> {
+    fopts
+        .sized(sect)
+        .map(|(_, _, _, name)| {
+            format!("::nmdel \"{}\"", fopts.qual_name(base, sect, name))
+        })
+        .collect()
+}
+
+/// Demangle a Rust or C++ symbol name into a form usable as an mdb symbol
+/// name, returning `None` if it is not mangled (or is malformed).
+pub(crate) fn demangle(name: &str) -> Option<String> {
+    let demangled = if let Ok(sym) = rustc_demangle::try_demangle(name) {
+        // The alternate form omits the trailing hash
+        format!("{:#}", sym)
+    } else {
+        // The parameters are kept, to tell overloads apart, though without
+        // the space following each comma
+        let sym = cpp_demangle::Symbol::new(name.as_bytes()).ok()?;
+        let dopts = cpp_demangle::DemangleOptions::new().no_return_type();
+        sym.demangle(&dopts).ok()?.replace(", ", ",")
+    };
+    // Whitespace (as in `operator new` or `(anonymous namespace)`) and the '.'
+    // module delimiter would garble the resulting mdb name.
+    Some(
+        demangled
+            .chars()
+            .map(|c| if c.is_whitespace() || c == '.' { '_' } else { c })
+            .collect(),
+    )
+}
+
+/// Precedence of a symbol when several share an address
+type SymRank = (bool, u8, bool);
+
+/// Candidates at one address are ranked first by whether they carry a size
+/// (unsized entries are often linker-defined markers), then by binding
+/// (global, then weak, then local), and finally by whether they are typed as
+/// a function.  Among equally ranked symbols, the first in the symbol table
+/// wins.
+fn sym_rank(sym: &goblin::elf::Sym) -> SymRank {
+    let bind = match sym.st_bind() {
+        goblin::elf::sym::STB_GLOBAL => 2,
+        goblin::elf::sym::STB_WEAK => 1,
+        _ => 0,
+    };
+    (sym.st_size != 0, bind, sym.is_function())
+}
+
+type SymMap = BTreeMap<u64, (SymRank, SymRes)>;
+
+/// Whether `hdr`, named `name`, holds pointers to constructors or
+/// destructors
+fn is_ctor_section(hdr: &SectionHeader, name: &str) -> bool {
+    matches!(hdr.sh_type, SHT_INIT_ARRAY | SHT_FINI_ARRAY | SHT_PREINIT_ARRAY)
+        || name == ".ctors"
+        || name == ".dtors"
+}
+
+/// A section whose symbols are being collected
+struct Pending {
+    sect: Section,
+    /// The st_value corresponding to the start of the section
+    vbase: u64,
+    /// Whether the section's symbols are to be collected
+    wanted: bool,
+    syms: SymMap,
+}
+
+/// Local symbols which are neither functions nor bookkeeping entries (for the
+/// section or source file), such as static variables and assembly labels
+fn is_local_data(sym: &goblin::elf::Sym) -> bool {
+    sym.st_bind() == STB_LOCAL
+        && sym.st_type() != STT_SECTION
+        && sym.st_type() != STT_FILE
+}
+
+/// The size of the section described by `hdr` once loaded.  For a section
+/// compressed in the object (`SHF_COMPRESSED`), `sh_size` is that of the
+/// compressed data, so the size is instead read from its compression header.
+/// Nothing else in the section needs to be read, so it is not decompressed.
+fn sect_size(
+    elf: &goblin::elf::Elf,
+    data: &[u8],
+    hdr: &SectionHeader,
+) -> goblin::error::Result<u64> {
+    if hdr.sh_flags & u64::from(SHF_COMPRESSED) == 0 {
+        return Ok(hdr.sh_size);
+    }
+    let container = if elf.is_64 { Container::Big } else { Container::Little };
+    let ctx = Ctx::new(container, Endian::from(elf.little_endian));
+    let chdr = CompressionHeader::parse(data, hdr.sh_offset as usize, ctx)?;
+    Ok(chdr.ch_size)
+}
+
+/// Names carrying no meaning for a debugger: empty ones, ARM mapping symbols
+/// (`$a`, `$d`, `$t`, ...) and compiler-local `.L` labels
+pub(crate) fn is_noise(name: &str) -> bool {
+    name.is_empty() || name.starts_with('$') || name.starts_with(".L")
+}
+
+/// The name of a versioned symbol (`foo@@VER` or `foo@VER`) without its
+/// version, which is no part of any name mdb would accept
+fn strip_version(name: &str) -> &str {
+    match name.find('@') {
+        Some(at) => &name[..at],
+        None => name,
+    }
+}
+
+/// Static symbols from different compilation units may share a name.  Leave
+/// non-local names alone, and give each repeated local name a numeric suffix
+/// (`foo_2`, `foo_3`, ...), in address order, so that it remains unique
+/// within the module.
+fn disambiguate_locals<'a>(maps: impl Iterator<Item = &'a mut SymMap>) {
+    let maps: Vec<&mut SymMap> = maps.collect();
+    let mut taken: HashSet<String> = maps
+        .iter()
+        .flat_map(|syms| syms.values())
+        .filter(|(rank, _)| rank.1 != 0)
+        .map(|(_, res)| res.name.clone())
+        .collect();
+    for syms in maps {
+        for (_, res) in syms.values_mut().filter(|(rank, _)| rank.1 == 0) {
+            if taken.contains(&res.name) {
+                let name = (2..)
+                    .map(|n| format!("{}_{}", res.name, n))
+                    .find(|name| !taken.contains(name))
+                    .unwrap();
+                res.name = name;
+            }
+            taken.insert(res.name.clone());
+        }
+    }
+}
+
+/// Files at least this large are mapped into memory, rather than read
+const MMAP_THRESHOLD: u64 = 4 << 20;
+
+/// Set in the environment to read every file, however large, rather than
+/// rely on it not changing while mapped
+const NO_MMAP_VAR: &str = "MDB_EDK2_NO_MMAP";
+
+/// The contents of a file, either read or mapped into memory
+pub(crate) enum FileData {
+    Read(Vec<u8>),
+    Mapped(memmap::Mmap),
+}
+
+impl std::ops::Deref for FileData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileData::Read(data) => data,
+            FileData::Mapped(map) => map,
+        }
+    }
+}
+
+/// Load the object file at `path` for parsing.  Most objects are small
+/// enough to read outright; only large ones are mapped into memory, which
+/// leaves them open to being truncated underfoot.
+pub(crate) fn map_file(path: &Path) -> Result<FileData> {
+    if let Some(target) = objdir::dangling(path) {
+        let msg = format!(
+            "{} is a symlink to {}, which does not exist",
+            path.display(),
+            target.display()
+        );
+        return Err(Error::new(ErrorKind::NotFound, msg));
+    }
+    let meta = path.metadata()?;
+    if !meta.is_file() {
+        let msg = format!("{} is not a regular file", path.display());
+        return Err(Error::new(ErrorKind::InvalidData, msg));
+    }
+    if meta.len() < MMAP_THRESHOLD || std::env::var_os(NO_MMAP_VAR).is_some() {
+        return Ok(FileData::Read(std::fs::read(path)?));
+    }
+    // Safety: the file is not expected to change while it is processed,
+    // and can be read instead, should that be in doubt
+    let map = unsafe { memmap::Mmap::map(&File::open(path)?)? };
+    Ok(FileData::Mapped(map))
+}
+
+/// Extract the symbols from the object at `path`, for a module loaded at
+/// `addr_start`.  The module's name, `_base`, is not needed to read them,
+/// but is kept for the sake of existing callers.
+pub fn process_file(
+    _base: &str,
+    path: &Path,
+    addr_start: u64,
+    opts: &Options,
+) -> Result<ObjSyms> {
+    process_file_rebased(path, addr_start, opts, None)
+}
+
+/// Like [`process_file`], but with the sections placed according to `rvas`,
+/// the section RVAs from the PE image the object was converted into (see
+/// [`pe::section_rvas`]), rather than by their `sh_addr`.  Sections absent
+/// from the image are moved by the same amount as `.text`.
+pub fn process_file_rebased(
+    path: &Path,
+    addr_start: u64,
+    opts: &Options,
+    rvas: Option<&HashMap<String, u64>>,
+) -> Result<ObjSyms> {
+    let map = map_file(path)?;
+    let elf = goblin::elf::Elf::parse(&map)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    let mut warnings = Vec::new();
+
+    let shdr_name = |ndx: usize| {
+        elf.shdr_strtab
+            .get(elf.section_headers[ndx].sh_name)
+            .and_then(|r| r.ok())
+    };
+    let shndxs = 0..elf.section_headers.len();
+    let text_shndx = match shndxs
+        .clone()
+        .find(|ndx| shdr_name(*ndx) == Some(".text"))
+    {
+        Some(ndx) => ndx,
+        None => {
+            // Without a .text, settle for another code section, such as a
+            // lone .text.startup
+            let is_code = |ndx: &usize| {
+                let flags = elf.section_headers[*ndx].sh_flags;
+                let code = u64::from(SHF_ALLOC | SHF_EXECINSTR);
+                flags & code == code
+            };
+            let ndx = shndxs
+                .clone()
+                .find(|ndx| {
+                    shdr_name(*ndx).is_some_and(|n| n.starts_with(".text"))
+                })
+                .or_else(|| shndxs.clone().find(is_code))
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, "No .text section found")
+                })?;
+            warnings.push(format!(
+                "no .text section, using {}",
+                shdr_name(ndx).unwrap_or("")
+            ));
+            ndx
+        }
+    };
+    if opts.verbose >= 1 {
+        eprintln!("{}: .text is section {}", path.display(), text_shndx);
+    }
+
+    // In a linked object, st_value is an address in the image (relative to
+    // the load base, like sh_addr), while in a relocatable object it is an
+    // offset into the containing section.
+    let is_rel = elf.header.e_type == goblin::elf::header::ET_REL;
+    // On 32-bit ARM, bit 0 of a function's st_value marks it as Thumb code,
+    // rather than being part of its address.
+    let is_arm = elf.header.e_machine == goblin::elf::header::EM_ARM;
+
+    // Line information is in terms of the addresses as linked, which only
+    // relocatable objects lack
+    let lines = match opts.source && !is_rel {
+        true => dwarf::line_table(&elf, &map),
+        false => None,
+    };
+
+    // goblin widens the fields of ELFCLASS32 objects (such as IA32 PEI
+    // modules) to u64 without sign extension, so the only concern is keeping
+    // relocated addresses within the 32-bit address space.
+    let addr_limit = if elf.is_64 { u64::MAX } else { u64::from(u32::MAX) };
+    let relocate = |base: u64, off: u64| {
+        base.checked_add(off).filter(|addr| *addr <= addr_limit)
+    };
+
+    // The offset of each section from the load address is its sh_addr,
+    // unless the PE image dictates otherwise.
+    let text_bias = match rvas {
+        None => 0,
+        Some(rvas) => rvas
+            .get(".text")
+            .ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, "No .text section in image")
+            })?
+            .wrapping_sub(elf.section_headers[text_shndx].sh_addr),
+    };
+    let sect_offset =
+        |name: &str, sh_addr: u64| match rvas.and_then(|rvas| rvas.get(name)) {
+            Some(rva) => *rva,
+            None => sh_addr.wrapping_add(text_bias),
+        };
+
+    // Symbols are collected per-section, so that zero-size entries are only
+    // stretched up to the end of the section containing them.
+    let mut results: BTreeMap<usize, Pending> = BTreeMap::new();
+    for (ndx, hdr) in elf.section_headers.iter().enumerate() {
+        let alloc = hdr.sh_flags & u64::from(SHF_ALLOC) != 0;
+        if ndx != text_shndx && !alloc {
+            continue;
+        }
+        let name = shdr_name(ndx).unwrap_or("");
+        let size = sect_size(&elf, &map, hdr).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("section {}: bad compression header: {}", name, e),
+            )
+        })?;
+        if opts.verbose >= 2 {
+            eprintln!(
+                "{}: section {} {}: addr {:x} size {:x} flags {:x}",
+                path.display(),
+                ndx,
+                name,
+                hdr.sh_addr,
+                size,
+                hdr.sh_flags
+            );
+        }
+        let wanted = ndx == text_shndx
+            || opts.data
+            || (opts.ctors && is_ctor_section(hdr, name));
+        // The section must fit entirely, up to its last byte.  Those which
+        // are not wanted hold no symbols to be misplaced, so are dropped.
+        let offset = sect_offset(name, hdr.sh_addr);
+        let start = relocate(addr_start, offset)
+            .filter(|start| relocate(*start, size.saturating_sub(1)).is_some());
+        let start = match start {
+            Some(start) => start,
+            None if !wanted => continue,
+            None => {
+                let msg = format!(
+                    "section {} at {:x} overflows the {}-bit address space",
+                    name,
+                    addr_start,
+                    if elf.is_64 { 64 } else { 32 }
+                );
+                return Err(Error::new(ErrorKind::InvalidData, msg));
+            }
+        };
+        let sect = Section {
+            name: name.to_string(),
+            start,
+            end: start + size,
+            syms: Vec::new(),
+            marks: Vec::new(),
+        };
+        let pending = Pending {
+            sect,
+            vbase: if is_rel { 0 } else { hdr.sh_addr },
+            wanted,
+            syms: SymMap::new(),
+        };
+        results.insert(ndx, pending);
+    }
+
+    // Stripped objects may retain only their dynamic symbols, which are
+    // consulted if the full symbol table offers nothing for .text.
+    let tables = [
+        (".symtab", &elf.syms, &elf.strtab),
+        (".dynsym", &elf.dynsyms, &elf.dynstrtab),
+    ];
+    for (table, symtab, strtab) in tables.iter() {
+        if !results[&text_shndx].syms.is_empty() {
+            break;
+        }
+        let mut passed = 0;
+        // Names which cannot be read, being out of the string table's bounds
+        // or not UTF-8, indicate a corrupt or truncated table
+        let mut bad_names = 0;
+        for sym in symtab.iter() {
+            let Pending { sect, vbase, syms, .. } =
+                match results.get_mut(&sym.st_shndx) {
+                    Some(pending) if pending.wanted => pending,
+                    _ => continue,
+                };
+            if opts.no_weak && sym.st_bind() == STB_WEAK {
+                continue;
+            }
+
+            let is_func = if sym.is_function() {
+                true
+            } else if sym.st_bind() == goblin::elf::sym::STB_GLOBAL {
+                // Functions implemented in assembly may not be properly
+                // typed, but can be recognized as code by their section
+                let flags = elf.section_headers[sym.st_shndx].sh_flags;
+                opts.asm_as_func && flags & u64::from(SHF_EXECINSTR) != 0
+            } else if opts.locals && is_local_data(&sym) {
+                false
+            } else {
+                continue;
+            };
+            passed += 1;
+            let value = match is_arm && is_func {
+                true => sym.st_value & !1,
+                false => sym.st_value,
+            };
+            // A symbol preceding its own section is nonsensical
+            let addr = match value
+                .checked_sub(*vbase)
+                .and_then(|off| relocate(sect.start, off))
+            {
+                Some(addr) => addr,
+                None => continue,
+            };
+            let name = match strtab.get(sym.st_name) {
+                Some(Ok(name)) => name,
+                _ => {
+                    bad_names += 1;
+                    continue;
+                }
+            };
+            let name = match opts.keep_version {
+                true => name,
+                false => strip_version(name),
+            };
+            if is_noise(name) {
+                continue;
+            }
+            let rank = sym_rank(&sym);
+            let name = match opts.demangle {
+                true => demangle(name).unwrap_or_else(|| name.to_string()),
+                false => name.to_string(),
+            };
+            if let Some((cur, res)) = syms.get_mut(&addr) {
+                if *cur >= rank {
+                    if opts.aliases {
+                        res.add_alias(name);
+                    }
+                    continue;
+                }
+            }
+            let source = match (&lines, is_func) {
+                (Some(lines), true) => lines.lookup(value),
+                _ => None,
+            };
+            let mut res = SymRes {
+                addr,
+                name,
+                size: sym.st_size,
+                is_func,
+                aliases: Vec::new(),
+                source,
+            };
+            if let Some((_, prev)) = syms.remove(&addr) {
+                if opts.aliases {
+                    res.absorb(prev);
+                }
+            }
+            syms.insert(addr, (rank, res));
+        }
+
+        if opts.verbose >= 1 {
+            eprintln!(
+                "{}: {} of {} {} symbols passed the section and binding \
+                 filters",
+                path.display(),
+                passed,
+                symtab.len(),
+                table
+            );
+            if bad_names > 0 {
+                eprintln!(
+                    "{}: {} {} symbols skipped for unreadable names",
+                    path.display(),
+                    bad_names,
+                    table
+                );
+            }
+        }
+    }
+
+    // Symbols running past the end of .text suggest that the object is stale
+    // relative to the image which was actually loaded.
+    let text = &results[&text_shndx];
+    let stray = text
+        .syms
+        .values()
+        .filter(|(_, res)| res.addr.saturating_add(res.size) > text.sect.end)
+        .count();
+    if stray > 0 {
+        let msg = format!(
+            "{} symbols extend beyond the end of .text; object may not match \
+             the loaded image",
+            stray
+        );
+        if opts.strict {
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+        warnings.push(msg);
+    }
+
+    // The entries of the constructor and destructor arrays are pointers,
+    // rarely with symbols of their own, so each is named after the function
+    // it points to, if that has a symbol.  Those of a relocatable object are
+    // yet to be filled in, leaving only any symbols it has.
+    let ctor_sects: Vec<usize> = results
+        .iter()
+        .filter(|(ndx, pending)| {
+            let hdr = &elf.section_headers[**ndx];
+            opts.ctors
+                && !is_rel
+                && hdr.sh_type != SHT_NOBITS
+                && is_ctor_section(hdr, &pending.sect.name)
+        })
+        .map(|(ndx, _)| *ndx)
+        .collect();
+    let ptr_size = if elf.is_64 { 8 } else { 4 };
+    for ndx in ctor_sects {
+        let hdr = &elf.section_headers[ndx];
+        let data = usize::try_from(hdr.sh_offset)
+            .ok()
+            .and_then(|off| {
+                map.get(off..off.checked_add(hdr.sh_size as usize)?)
+            })
+            .unwrap_or(&[]);
+        let mut entries = Vec::new();
+        for (idx, entry) in data.chunks_exact(ptr_size).enumerate() {
+            let mut bytes = [0u8; 8];
+            match elf.little_endian {
+                true => bytes[..ptr_size].copy_from_slice(entry),
+                false => bytes[8 - ptr_size..].copy_from_slice(entry),
+            }
+            let value = match elf.little_endian {
+                true => u64::from_le_bytes(bytes),
+                false => u64::from_be_bytes(bytes),
+            };
+            let value = if is_arm { value & !1 } else { value };
+            // Find the function in whichever section it lies
+            let target = results.values().find_map(|p| {
+                let addr = relocate(p.sect.start, value.checked_sub(p.vbase)?)?;
+                let (_, res) = p.syms.get(&addr).filter(|(_, r)| r.is_func)?;
+                Some(res.name.clone())
+            });
+            if let Some(target) = target {
+                let slot = results[&ndx].sect.start + (idx * ptr_size) as u64;
+                entries.push((slot, target));
+            }
+        }
+        let Pending { sect, syms, .. } = results.get_mut(&ndx).unwrap();
+        let prefix = sect.name.trim_start_matches('.').to_string();
+        for (slot, target) in entries {
+            syms.entry(slot).or_insert_with(|| {
+                let res = SymRes {
+                    addr: slot,
+                    name: format!("{}_{}", prefix, target),
+                    size: ptr_size as u64,
+                    is_func: false,
+                    aliases: Vec::new(),
+                    source: None,
+                };
+                ((true, 0, false), res)
+            });
+        }
+    }
+
+    // Whether an absolute symbol is an address, or an offset into the
+    // image, or not an address at all, is beyond knowing, so those wanted
+    // are emitted as objects, as instructed.
+    let mut abs = BTreeMap::new();
+    if opts.abs {
+        let abs_syms =
+            elf.syms.iter().filter(|sym| sym.st_shndx == SHN_ABS as usize);
+        for sym in abs_syms {
+            match (sym.st_type(), sym.st_bind()) {
+                (STT_FILE | STT_SECTION, _) => continue,
+                (_, STB_LOCAL) if !opts.locals => continue,
+                _ => {}
+            }
+            let addr = match opts.abs_relocate {
+                true => relocate(addr_start, sym.st_value),
+                false => Some(sym.st_value).filter(|addr| *addr <= addr_limit),
+            };
+            let (addr, name) = match (addr, elf.strtab.get(sym.st_name)) {
+                (Some(addr), Some(Ok(name))) if !is_noise(name) => (addr, name),
+                _ => continue,
+            };
+            abs.entry(addr).or_insert_with(|| SymRes {
+                addr,
+                name: name.to_string(),
+                size: sym.st_size,
+                is_func: false,
+                aliases: Vec::new(),
+                source: None,
+            });
+        }
+    }
+    let abs = abs.into_values().collect();
+
+    if opts.locals {
+        disambiguate_locals(results.values_mut().map(|p| &mut p.syms));
+    }
+
+    let mut sections: Vec<(usize, Section)> = results
+        .into_iter()
+        .map(|(ndx, Pending { mut sect, syms, .. })| {
+            sect.syms = syms.into_values().map(|(_, res)| res).collect();
+            (ndx, sect)
+        })
+        .collect();
+    sections.sort_by_key(|(_, sect)| sect.start);
+    let text = sections.iter().position(|(ndx, _)| *ndx == text_shndx).unwrap();
+    let sections: Vec<Section> =
+        sections.into_iter().map(|(_, sect)| sect).collect();
+    // Unsized symbols past the end of their section cannot be stretched, so
+    // are not output
+    for sect in sections.iter() {
+        for res in sect.syms.iter() {
+            if res.size == 0 && res.addr > sect.end {
+                warnings.push(format!(
+                    "skipping {} at {:x}: beyond section end {:x}",
+                    res.name, res.addr, sect.end
+                ));
+            }
+        }
+    }
+    Ok(ObjSyms { sections, text, abs, warnings })
+}
+
+/// Describe the sections of the ELF object at `path`: the name, address,
+/// size and flags of each, along with how many symbols it contains.  This
+/// shows what `process_file` has to work with, when symbols go missing.
+pub fn inspect_file(path: &Path) -> Result<String> {
+    let map = map_file(path)?;
+    let elf = goblin::elf::Elf::parse(&map)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+    let mut counts = vec![0usize; elf.section_headers.len()];
+    for sym in elf.syms.iter().chain(elf.dynsyms.iter()) {
+        if let Some(count) = counts.get_mut(sym.st_shndx) {
+            *count += 1;
+        }
+    }
+    let mut out = format!(
+        "{:>4} {:<24} {:>16} {:>8} {:>8} {:>6}\n",
+        "ndx", "name", "addr", "size", "flags", "syms"
+    );
+    for (ndx, hdr) in elf.section_headers.iter().enumerate() {
+        let name = elf.shdr_strtab.get(hdr.sh_name).and_then(|r| r.ok());
+        out.push_str(&format!(
+            "{:>4} {:<24} {:>16x} {:>8x} {:>8x} {:>6}\n",
+            ndx,
+            name.unwrap_or(""),
+            hdr.sh_addr,
+            hdr.sh_size,
+            hdr.sh_flags,
+            counts[ndx]
+        ));
+    }
+    Ok(out)
+}
+
+/// For an ELF object at `path` stripped of its symbol table, find the file
+/// named by its `.gnu_debuglink` section within the first of `dirs` holding
+/// it, checking that the CRC recorded alongside the name matches.  Objects
+/// which retain their symbols, or carry no link, yield `None`.
+pub fn follow_debuglink(
+    path: &Path,
+    dirs: &[PathBuf],
+) -> Result<Option<PathBuf>> {
+    let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+    let map = map_file(path)?;
+    let elf =
+        goblin::elf::Elf::parse(&map).map_err(|e| invalid(e.to_string()))?;
+    // The table always starts with a null entry
+    if elf.syms.len() > 1 {
+        return Ok(None);
+    }
+    let hdr = match elf.section_headers.iter().find(|hdr| {
+        elf.shdr_strtab.get(hdr.sh_name).and_then(|r| r.ok())
+            == Some(".gnu_debuglink")
+    }) {
+        Some(hdr) => hdr,
+        None => return Ok(None),
+    };
+
+    // The file name is NUL-terminated, then padded to a 4-byte boundary
+    // before the CRC
+    let data = usize::try_from(hdr.sh_offset)
+        .ok()
+        .zip(usize::try_from(hdr.sh_size).ok())
+        .and_then(|(off, size)| map.get(off..off.checked_add(size)?))
+        .ok_or_else(|| invalid("truncated .gnu_debuglink".to_string()))?;
+    let nul = data.iter().position(|b| *b == 0);
+    let (name, crc) = match nul.map(|nul| (nul, (nul + 4) & !3)) {
+        Some((nul, at)) if data.len() >= at + 4 => {
+            let bytes = [data[at], data[at + 1], data[at + 2], data[at + 3]];
+            let crc = match elf.little_endian {
+                true => u32::from_le_bytes(bytes),
+                false => u32::from_be_bytes(bytes),
+            };
+            (String::from_utf8_lossy(&data[..nul]), crc)
+        }
+        _ => return Err(invalid("malformed .gnu_debuglink".to_string())),
+    };
+
+    let linked = dirs
+        .iter()
+        .map(|dir| dir.join(name.as_ref()))
+        .find(|linked| linked.exists())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("linked file {} not found", name),
+            )
+        })?;
+    let mut sum = flate2::Crc::new();
+    sum.update(&map_file(&linked)?);
+    if sum.sum() != crc {
+        return Err(invalid(format!(
+            "CRC of {} does not match its .gnu_debuglink",
+            linked.display()
+        )));
+    }
+    Ok(Some(linked))
+}
+
+/// Read the GNU build-id note of the ELF object at `path`, if it has one.
+pub fn build_id(path: &Path) -> Result<Option<Vec<u8>>> {
+    let map = map_file(path)?;
+    let elf = goblin::elf::Elf::parse(&map)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    let notes = elf.iter_note_sections(&map, Some(".note.gnu.build-id"));
+    Ok(notes.into_iter().flatten().filter_map(|note| note.ok()).find_map(
+        |note| match (note.n_type, note.name) {
+            (NT_GNU_BUILD_ID, "GNU") => Some(note.desc.to_vec()),
+            _ => None,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testelf::{testdata, ElfBuilder, TempDir};
+
+    fn sym(addr: u64, name: &str, size: u64, is_func: bool) -> SymRes {
+        SymRes {
+            addr,
+            name: name.to_string(),
+            size,
+            is_func,
+            aliases: Vec::new(),
+            source: None,
+        }
+    }
+
+    fn sizes(syms: &[SymRes], end: u64) -> Vec<(u64, u64)> {
+        compute_sizes(syms, end).iter().map(|(a, s, _)| (*a, *s)).collect()
+    }
+
+    /// Process the object built by `elf`, loaded at `addr`
+    fn process(elf: &ElfBuilder, addr: u64, opts: &Options) -> Result<ObjSyms> {
+        let dir = TempDir::new();
+        let path = dir.join("Foo.debug");
+        elf.write(&path);
+        process_file("Foo", &path, addr, opts)
+    }
+
+    /// The (address, name, size) of each symbol of `sect`
+    fn syms(sect: &Section) -> Vec<(u64, &str, u64)> {
+        sect.syms.iter().map(|r| (r.addr, &*r.name, r.size)).collect()
+    }
+
+    #[test]
+    fn stretch_to_next_symbol_and_section_end() {
+        let syms = [
+            sym(0x1000, "a", 0, true),
+            sym(0x1010, "b", 4, true),
+            sym(0x1020, "c", 0, true),
+        ];
+        // The last entry runs to the end of the section
+        assert_eq!(
+            sizes(&syms, 0x1100),
+            [(0x1000, 0x10), (0x1010, 4), (0x1020, 0xe0)]
+        );
+    }
+
+    #[test]
+    fn text_placed_at_its_sh_addr() {
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0x1000, 0x100);
+        elf.func(text, "foo", 0x1010, 0x10);
+        let obj = process(&elf, 0x7f00_0000, &Options::default()).unwrap();
+        let text = obj.text();
+        assert_eq!((text.start, text.end), (0x7f00_1000, 0x7f00_1100));
+        assert_eq!(syms(text), [(0x7f00_1010, "foo", 0x10)]);
+    }
+
+    #[test]
+    fn skip_unsized_beyond_section_end() {
+        let syms = [sym(0x1000, "a", 0, true), sym(0x1200, "b", 0, true)];
+        assert_eq!(sizes(&syms, 0x1100), [(0x1000, 0x100)]);
+
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x100);
+        elf.func(text, "a", 0, 0);
+        elf.func(text, "b", 0x200, 0);
+        let obj = process(&elf, 0x1000, &Options::default()).unwrap();
+        assert_eq!(
+            obj.warnings.last().unwrap(),
+            "skipping b at 1200: beyond section end 1100"
+        );
+    }
+
+    #[test]
+    fn sized_global_wins_over_unsized_weak() {
+        use goblin::elf::sym::{STB_LOCAL, STT_FUNC};
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x100);
+        elf.sym(text, "weak", 0x10, 0, STB_WEAK, STT_FUNC);
+        elf.func(text, "global", 0x10, 8);
+        elf.sym(text, "local", 0x10, 8, STB_LOCAL, STT_FUNC);
+        let obj = process(&elf, 0, &Options::default()).unwrap();
+        assert_eq!(syms(obj.text()), [(0x10, "global", 8)]);
+
+        let opts = Options { aliases: true, ..Default::default() };
+        let obj = process(&elf, 0, &opts).unwrap();
+        assert_eq!(obj.text().syms[0].aliases, ["weak", "local"]);
+    }
+
+    #[test]
+    fn elf32_overflow_only_fails_wanted_sections() {
+        let mut elf = ElfBuilder::new();
+        elf.is_64 = false;
+        elf.machine = goblin::elf::header::EM_386;
+        let text = elf.code(".text", 0, 0x10);
+        elf.section(".hash", u64::from(SHF_ALLOC), 0x100, 0x40);
+        elf.func(text, "foo", 0, 8);
+        let obj = process(&elf, 0xffff_fff0, &Options::default()).unwrap();
+        assert_eq!((obj.text().start, obj.text().end), (0xffff_fff0, 1 << 32));
+        assert_eq!(obj.sections.len(), 1);
+        assert_eq!(
+            format_nmadd(obj.text(), "Foo", &FmtOpts::default()),
+            ["fffffff0::nmadd -f -s 8 \"Foo.foo\""]
+        );
+
+        let opts = Options { data: true, ..Default::default() };
+        let err = process(&elf, 0xffff_fff0, &opts).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "section .hash at fffffff0 overflows the 32-bit address space"
+        );
+    }
+
+    #[test]
+    fn demangle_keeps_overloads_apart() {
+        assert_eq!(demangle("_ZN2Ov1fEi").unwrap(), "Ov::f(int)");
+        assert_eq!(demangle("_ZN2Ov1fEc").unwrap(), "Ov::f(char)");
+        assert_eq!(demangle("_ZN2Ov1fEic").unwrap(), "Ov::f(int,char)");
+        assert_eq!(
+            demangle("_ZN12_GLOBAL__N_11gEv").unwrap(),
+            "(anonymous_namespace)::g()"
+        );
+        assert_eq!(
+            demangle("_ZN4core3fmt5write17h0123456789abcdefE").unwrap(),
+            "core::fmt::write"
+        );
+        assert_eq!(demangle("EfiMain"), None);
+    }
+
+    #[test]
+    fn sections_placed_at_efi_rvas() {
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0x240, 0x100);
+        let data = elf.data(".data", 0x400, 0x10);
+        let bss = elf.data(".bss", 0x500, 0x10);
+        elf.func(text, "helper", 0x244, 3);
+        elf.object(data, "gCounter", 0x400, 4);
+        elf.object(bss, "gState", 0x500, 4);
+        let dir = TempDir::new();
+        let path = dir.join("Foo.debug");
+        elf.write(&path);
+
+        // At runtime, each section is at the load address plus its RVA, and
+        // .bss, absent from the image, moves along with .text
+        let rvas = pe::section_rvas(&testdata("pe/Foo.efi")).unwrap();
+        assert_eq!((rvas[".text"], rvas[".data"]), (0x1000, 0x3000));
+        let opts = Options { data: true, ..Default::default() };
+        let base = 0x7f00_0000;
+        let obj =
+            process_file_rebased(&path, base, &opts, Some(&rvas)).unwrap();
+        let addrs: Vec<(&str, u64)> = obj
+            .sections
+            .iter()
+            .flat_map(|s| s.syms.iter().map(|r| (&*r.name, r.addr)))
+            .collect();
+        assert_eq!(
+            addrs,
+            [
+                ("helper", base + 0x1004),
+                ("gState", base + 0x1000 + 0x500 - 0x240),
+                ("gCounter", base + 0x3000),
+            ]
+        );
+    }
+
+    #[test]
+    fn dynsym_used_without_symtab() {
+        let path = testdata("dynsym/Dyn.debug");
+        let opts = Options { data: true, ..Default::default() };
+        let obj = process_file("Dyn", &path, 0x10_0000, &opts).unwrap();
+        assert_eq!(
+            syms(obj.text()),
+            [(0x10_0250, "foo", 12), (0x10_025c, "bar", 19)]
+        );
+        let data = obj.sections.iter().find(|s| s.name == ".data").unwrap();
+        assert_eq!(syms(data), [(0x10_1450, "g", 4)]);
+    }
+
+    #[test]
+    fn arm_thumb_bit_cleared() {
+        let mut elf = ElfBuilder::new();
+        elf.is_64 = false;
+        elf.machine = goblin::elf::header::EM_ARM;
+        let text = elf.code(".text", 0, 0x100);
+        elf.func(text, "thumb", 0x11, 6);
+        // Only functions are interworking addresses
+        elf.object(text, "table", 0x21, 4);
+        let opts = Options::default();
+        let obj = process(&elf, 0x1000, &opts).unwrap();
+        assert_eq!(
+            syms(obj.text()),
+            [(0x1010, "thumb", 6), (0x1021, "table", 4)]
+        );
+
+        // Elsewhere, an odd address is as it is
+        elf.machine = goblin::elf::header::EM_386;
+        let obj = process(&elf, 0x1000, &opts).unwrap();
+        assert_eq!(syms(obj.text())[0], (0x1011, "thumb", 6));
+    }
+
+    #[test]
+    fn untyped_code_as_function() {
+        use goblin::elf::sym::{STB_GLOBAL, STT_NOTYPE};
+        let mut elf = ElfBuilder::new();
+        elf.is_64 = false;
+        elf.machine = goblin::elf::header::EM_ARM;
+        let text = elf.code(".text", 0, 0x100);
+        let data = elf.data(".data", 0x100, 0x10);
+        elf.sym(text, "AsmThumb", 0x11, 6, STB_GLOBAL, STT_NOTYPE);
+        elf.sym(data, "AsmTable", 0x100, 4, STB_GLOBAL, STT_NOTYPE);
+        let kinds = |opts: &Options| -> Vec<(u64, bool)> {
+            let obj = process(&elf, 0x1000, opts).unwrap();
+            obj.sections
+                .iter()
+                .flat_map(|s| s.syms.iter().map(|r| (r.addr, r.is_func)))
+                .collect()
+        };
+        let opts = Options { data: true, ..Default::default() };
+        assert_eq!(kinds(&opts), [(0x1011, false), (0x1100, false)]);
+        // Only the one in code is taken for a function, and so for Thumb
+        let opts = Options { asm_as_func: true, ..opts };
+        assert_eq!(kinds(&opts), [(0x1010, true), (0x1100, false)]);
+    }
+
+    #[test]
+    fn quote_in_name_sanitized() {
+        let sect = Section {
+            name: ".text".to_string(),
+            start: 0x1000,
+            end: 0x1100,
+            syms: vec![sym(0x1000, "say\"hi\"`x.y\\z", 0x10, true)],
+            marks: Vec::new(),
+        };
+        assert_eq!(
+            format_nmadd(&sect, "Foo", &FmtOpts::default()),
+            ["1000::nmadd -f -s 10 \"Foo.say_hi__x_y_z\""]
+        );
+    }
+
+    #[test]
+    fn noise_symbols_dropped() {
+        use goblin::elf::sym::{STB_LOCAL, STT_NOTYPE, STT_OBJECT};
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x100);
+        let data = elf.data(".data", 0x100, 0x10);
+        elf.func(text, "real", 0, 0x10);
+        elf.sym(text, "$t", 0, 0, STB_LOCAL, STT_NOTYPE);
+        elf.sym(text, ".Ltmp0", 0x8, 0, STB_LOCAL, STT_NOTYPE);
+        elf.sym(data, "$d", 0x100, 0, STB_LOCAL, STT_NOTYPE);
+        elf.sym(data, "", 0x104, 0, STB_LOCAL, STT_NOTYPE);
+        elf.sym(data, "counter", 0x108, 4, STB_LOCAL, STT_OBJECT);
+        let opts = Options { data: true, locals: true, ..Default::default() };
+        let obj = process(&elf, 0, &opts).unwrap();
+        let names: Vec<&str> = obj
+            .sections
+            .iter()
+            .flat_map(|s| s.syms.iter().map(|r| &*r.name))
+            .collect();
+        assert_eq!(names, ["real", "counter"]);
+    }
+
+    #[test]
+    fn symbols_beyond_text_warn_or_fail() {
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x100);
+        elf.func(text, "fits", 0, 0x10);
+        elf.func(text, "stale", 0x180, 0x20);
+        let msg = "1 symbols extend beyond the end of .text; object may not \
+                   match the loaded image";
+        let obj = process(&elf, 0x1000, &Options::default()).unwrap();
+        assert_eq!(obj.warnings, [msg]);
+        assert_eq!(obj.text().syms.len(), 2);
+
+        let opts = Options { strict: true, ..Default::default() };
+        let err = process(&elf, 0x1000, &opts).err().unwrap();
+        assert_eq!(err.to_string(), msg);
+    }
+
+    #[test]
+    fn aliases_emitted_at_one_address() {
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x100);
+        elf.func(text, "memcpy", 0x10, 0x20);
+        elf.func(text, "CopyMem", 0x10, 0x20);
+        let fopts = FmtOpts::default();
+        let obj = process(&elf, 0x1000, &Options::default()).unwrap();
+        assert_eq!(
+            format_nmadd(obj.text(), "Foo", &fopts),
+            ["1010::nmadd -f -s 20 \"Foo.memcpy\""]
+        );
+
+        let opts = Options { aliases: true, ..Default::default() };
+        let obj = process(&elf, 0x1000, &opts).unwrap();
+        assert_eq!(
+            format_nmadd(obj.text(), "Foo", &fopts),
+            [
+                "1010::nmadd -f -s 20 \"Foo.memcpy\"",
+                "1010::nmadd -f -s 20 \"Foo.CopyMem\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn lone_text_startup_stands_in_for_text() {
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text.startup", 0x100, 0x40);
+        elf.func(text, "main", 0x100, 0x10);
+        let obj = process(&elf, 0x1000, &Options::default()).unwrap();
+        assert_eq!(obj.text().name, ".text.startup");
+        assert_eq!(syms(obj.text()), [(0x1100, "main", 0x10)]);
+        assert_eq!(obj.warnings, ["no .text section, using .text.startup"]);
+    }
+
+    #[test]
+    fn version_suffix_stripped() {
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x100);
+        elf.func(text, "foo@@VER_1", 0, 0x10);
+        elf.func(text, "bar@VER_0", 0x10, 0x10);
+        let obj = process(&elf, 0, &Options::default()).unwrap();
+        assert_eq!(syms(obj.text()), [(0, "foo", 0x10), (0x10, "bar", 0x10)]);
+
+        let opts = Options { keep_version: true, ..Default::default() };
+        let obj = process(&elf, 0, &opts).unwrap();
+        assert_eq!(obj.text().syms[0].name, "foo@@VER_1");
+    }
+
+    #[test]
+    fn leading_underscore_stripped_under_flag() {
+        let sect = Section {
+            name: ".text".to_string(),
+            start: 0x1000,
+            end: 0x1020,
+            syms: vec![
+                sym(0x1000, "_EntryPoint", 0x10, true),
+                sym(0x1010, "_", 0x10, true),
+            ],
+            marks: Vec::new(),
+        };
+        let mut fopts = FmtOpts::default();
+        assert_eq!(
+            fopts.output_names(&sect, "Foo"),
+            ["Foo._EntryPoint", "Foo._"]
+        );
+        fopts.strip_underscore = true;
+        assert_eq!(
+            fopts.output_names(&sect, "Foo"),
+            ["Foo.EntryPoint", "Foo._"]
+        );
+    }
+
+    #[test]
+    fn compressed_section_sized_by_its_header() {
+        use crate::testelf::Sect;
+        use goblin::elf::section_header::{SHF_WRITE, SHT_PROGBITS};
+        // ELFCOMPRESS_ZLIB, reserved, ch_size and ch_addralign, then the
+        // (here absent) compressed data
+        let mut chdr = Vec::new();
+        chdr.extend_from_slice(&1u32.to_le_bytes());
+        chdr.extend_from_slice(&0u32.to_le_bytes());
+        chdr.extend_from_slice(&0x200u64.to_le_bytes());
+        chdr.extend_from_slice(&8u64.to_le_bytes());
+        let mut elf = ElfBuilder::new();
+        elf.code(".text", 0, 0x100);
+        let data = elf.add_section(Sect {
+            name: ".data".to_string(),
+            sh_type: SHT_PROGBITS,
+            flags: u64::from(SHF_ALLOC | SHF_WRITE | SHF_COMPRESSED),
+            addr: 0x100,
+            data: chdr,
+            size: None,
+        });
+        elf.object(data, "gTable", 0x100, 0);
+        let opts = Options { data: true, ..Default::default() };
+        let obj = process(&elf, 0x1000, &opts).unwrap();
+        let data = &obj.sections[1];
+        assert_eq!((data.start, data.end), (0x1100, 0x1300));
+        assert_eq!(sizes(&data.syms, data.end), [(0x1100, 0x200)]);
+    }
+
+    #[test]
+    fn only_and_exclude_patterns() {
+        let sect = Section {
+            name: ".text".to_string(),
+            start: 0x1000,
+            end: 0x1030,
+            syms: vec![
+                sym(0x1000, "Foo", 0, true),
+                sym(0x1010, "Bar", 0, true),
+                sym(0x1020, "Baz", 0, true),
+            ],
+            marks: Vec::new(),
+        };
+        let pats = |pats: &[&str]| -> Vec<glob::Pattern> {
+            pats.iter().map(|p| glob::Pattern::new(p).unwrap()).collect()
+        };
+        let mut fopts =
+            FmtOpts { only: Some(pats(&["Foo", "Ba*"])), ..Default::default() };
+        assert_eq!(fopts.output_names(&sect, "M").len(), 3);
+        // Exclusion wins, and the sizes are still those among all symbols
+        fopts.exclude = pats(&["Bar"]);
+        assert_eq!(
+            format_nmadd(&sect, "M", &fopts),
+            [
+                "1000::nmadd -f -s 10 \"M.Foo\"",
+                "1020::nmadd -f -s 10 \"M.Baz\"",
+            ]
+        );
+        fopts.only = Some(pats(&["Foo"]));
+        assert_eq!(fopts.output_names(&sect, "M"), ["M.Foo"]);
+        fopts.only = None;
+        assert_eq!(fopts.output_names(&sect, "M"), ["M.Foo", "M.Baz"]);
+    }
+
+    #[test]
+    fn debuglink_followed_with_crc_check() {
+        use crate::testelf::Sect;
+        use goblin::elf::section_header::SHT_PROGBITS;
+        let dir = TempDir::new();
+        let mut full = ElfBuilder::new();
+        let text = full.code(".text", 0, 0x100);
+        full.func(text, "foo", 0, 0x10);
+        let full = full.build();
+        std::fs::write(dir.join("Foo.full"), &full).unwrap();
+
+        let stripped = |crc: u32| {
+            let mut link = b"Foo.full\0\0\0\0".to_vec();
+            link.extend_from_slice(&crc.to_le_bytes());
+            let mut elf = ElfBuilder::new();
+            elf.code(".text", 0, 0x100);
+            elf.add_section(Sect {
+                name: ".gnu_debuglink".to_string(),
+                sh_type: SHT_PROGBITS,
+                flags: 0,
+                addr: 0,
+                data: link,
+                size: None,
+            });
+            let path = dir.join("Foo.debug");
+            elf.write(&path);
+            path
+        };
+        let mut sum = flate2::Crc::new();
+        sum.update(&full);
+        let dirs = [dir.path().to_path_buf()];
+        let path = stripped(sum.sum());
+        assert_eq!(
+            follow_debuglink(&path, &dirs).unwrap(),
+            Some(dir.join("Foo.full"))
+        );
+
+        let path = stripped(sum.sum() ^ 1);
+        let err = follow_debuglink(&path, &dirs).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "CRC of {} does not match its .gnu_debuglink",
+                dir.join("Foo.full").display()
+            )
+        );
+
+        // Objects with symbols of their own are used as they are
+        let full = dir.join("Foo.full");
+        assert_eq!(follow_debuglink(&full, &dirs).unwrap(), None);
+    }
+
+    #[test]
+    fn absolute_symbols() {
+        use goblin::elf::sym::{STB_LOCAL, STT_NOTYPE};
+        let abs = SHN_ABS as u16;
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x100);
+        elf.func(text, "foo", 0, 0x10);
+        elf.object(abs, "LOCAL_APIC", 0xfee0_0000, 0x400);
+        elf.sym(abs, "kPageSize", 0x1000, 0, STB_LOCAL, STT_NOTYPE);
+        elf.sym(abs, "foo.c", 0, 0, STB_LOCAL, STT_FILE);
+        let abs = |opts: &Options| -> Vec<(u64, String)> {
+            let obj = process(&elf, 0x10_0000, opts).unwrap();
+            obj.abs.iter().map(|r| (r.addr, r.name.clone())).collect()
+        };
+        assert!(abs(&Options::default()).is_empty());
+        let opts = Options { abs: true, ..Default::default() };
+        assert_eq!(abs(&opts), [(0xfee0_0000, "LOCAL_APIC".to_string())]);
+        let opts = Options { locals: true, ..opts };
+        assert_eq!(abs(&opts).len(), 2);
+        let opts = Options { abs_relocate: true, ..opts };
+        assert_eq!(abs(&opts)[0], (0x10_1000, "kPageSize".to_string()));
+    }
+
+    #[test]
+    fn zero_size_kept_without_stretching() {
+        let sect = Section {
+            name: ".text".to_string(),
+            start: 0x1000,
+            end: 0x1100,
+            syms: vec![
+                sym(0x1000, "Foo", 0, true),
+                sym(0x1010, "Bar", 8, true),
+            ],
+            marks: Vec::new(),
+        };
+        let mut fopts = FmtOpts::default();
+        assert_eq!(
+            format_nmadd(&sect, "M", &fopts),
+            ["1000::nmadd -f -s 10 \"M.Foo\"", "1010::nmadd -f -s 8 \"M.Bar\"",]
+        );
+        fopts.no_stretch = true;
+        assert_eq!(
+            format_nmadd(&sect, "M", &fopts),
+            ["1000::nmadd -f -s 0 \"M.Foo\"", "1010::nmadd -f -s 8 \"M.Bar\"",]
+        );
+    }
+
+    #[test]
+    fn end_given_as_addr_plus_size() {
+        let sect = Section {
+            name: ".text".to_string(),
+            start: 0x1000,
+            end: 0x1100,
+            syms: vec![
+                sym(0x1000, "Foo", 0, true),
+                sym(0x1010, "Bar", 8, true),
+            ],
+            marks: Vec::new(),
+        };
+        // Decimal sizes leave the end, an address, in hex
+        let fopts =
+            FmtOpts { use_end: true, radix: Radix::Dec, ..Default::default() };
+        assert_eq!(
+            format_nmadd(&sect, "M", &fopts),
+            [
+                "1000::nmadd -f -e 1010 \"M.Foo\"",
+                "1010::nmadd -f -e 1018 \"M.Bar\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn weak_functions_dropped_under_no_weak() {
+        use goblin::elf::sym::STT_FUNC;
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x100);
+        elf.func(text, "foo", 0, 0x10);
+        elf.sym(text, "DebugPrint", 0x10, 0x10, STB_WEAK, STT_FUNC);
+        let obj = process(&elf, 0, &Options::default()).unwrap();
+        assert_eq!(
+            syms(obj.text()),
+            [(0, "foo", 0x10), (0x10, "DebugPrint", 0x10)]
+        );
+        let opts = Options { no_weak: true, ..Default::default() };
+        let obj = process(&elf, 0, &opts).unwrap();
+        assert_eq!(syms(obj.text()), [(0, "foo", 0x10)]);
+    }
+
+    #[test]
+    fn output_names_match_formatters() {
+        let mut foo = sym(0x1000, "_Foo", 0, true);
+        foo.aliases.push("FooAlias".to_string());
+        let sect = Section {
+            name: ".text".to_string(),
+            start: 0x1000,
+            end: 0x1040,
+            syms: vec![
+                foo,
+                sym(0x1010, "Tiny", 2, true),
+                sym(0x1020, "gData", 8, false),
+                sym(0x1030, "Bar", 0, true),
+            ],
+            marks: vec![sym(0x1000, "Foo_start", 0, false)],
+        };
+        let pats = |pats: &[&str]| -> Vec<glob::Pattern> {
+            pats.iter().map(|p| glob::Pattern::new(p).unwrap()).collect()
+        };
+        let fopts = FmtOpts {
+            min_size: 4,
+            kind: Kind::Func,
+            strip_underscore: true,
+            exclude: pats(&["Bar"]),
+            ..Default::default()
+        };
+        let names = fopts.output_names(&sect, "M");
+        assert_eq!(names, ["M.Foo", "M.FooAlias", "M.Foo_start"]);
+        let quoted =
+            |line: &String| line.split('"').nth(1).unwrap().to_string();
+        let nmadd: Vec<String> =
+            format_nmadd(&sect, "M", &fopts).iter().map(quoted).collect();
+        let nmdel: Vec<String> =
+            format_nmdel(&sect, "M", &fopts).iter().map(quoted).collect();
+        let json: Vec<String> = format_json(&sect, "M", &fopts)
+            .iter()
+            .map(|obj| format!("M.{}", obj["name"].as_str().unwrap()))
+            .collect();
+        let table: Vec<String> = format_table(&sect, "M", &fopts)
+            .iter()
+            .map(|row| format!("M.{}", row.rsplit(' ').next().unwrap()))
+            .collect();
+        assert_eq!(nmadd, names);
+        assert_eq!(nmdel, names);
+        assert_eq!(json, names);
+        assert_eq!(table, names);
+    }
+
+    #[test]
+    fn unreadable_names_skipped() {
+        use crate::testelf::Sym;
+        use goblin::elf::sym::{STB_GLOBAL, STT_FUNC};
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x100);
+        elf.func(text, "foo", 0, 0x10);
+        // Past the end of .strtab
+        elf.add_sym(Sym {
+            name: "lost".to_string(),
+            shndx: text,
+            value: 0x10,
+            size: 0x10,
+            bind: STB_GLOBAL,
+            typ: STT_FUNC,
+            st_name: Some(0x10_0000),
+        });
+        elf.func(text, "bar", 0x20, 0x10);
+        let obj = process(&elf, 0, &Options::default()).unwrap();
+        assert_eq!(syms(obj.text()), [(0, "foo", 0x10), (0x20, "bar", 0x10)]);
+    }
+
+    #[test]
+    fn relocatable_values_are_section_offsets() {
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0x400, 0x100);
+        elf.func(text, "foo", 0x10, 8);
+        let obj = process(&elf, 0x1000, &Options::default()).unwrap();
+        assert!(obj.text().syms.is_empty());
+
+        elf.e_type = goblin::elf::header::ET_REL;
+        let obj = process(&elf, 0x1000, &Options::default()).unwrap();
+        assert_eq!(syms(obj.text()), [(0x1410, "foo", 8)]);
+    }
+
+    #[test]
+    fn limit_keeps_largest() {
+        let obj = || ObjSyms {
+            sections: vec![Section {
+                name: ".text".to_string(),
+                start: 0x1000,
+                end: 0x1060,
+                syms: vec![
+                    sym(0x1000, "a", 0, true),
+                    sym(0x1020, "b", 8, true),
+                    sym(0x1030, "c", 0x20, true),
+                    sym(0x1050, "d", 8, true),
+                ],
+                marks: Vec::new(),
+            }],
+            text: 0,
+            abs: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let fopts = FmtOpts::default();
+        let mut three = obj();
+        three.limit(3, &fopts);
+        // Ties go to the lower address
+        assert_eq!(
+            syms(three.text()),
+            [(0x1000, "a", 0x20), (0x1020, "b", 8), (0x1030, "c", 0x20)]
+        );
+        // a keeps the size it was stretched to, rather than reaching c
+        let mut two = obj();
+        two.limit(2, &fopts);
+        assert_eq!(
+            format_nmadd(two.text(), "M", &fopts),
+            ["1000::nmadd -f -s 20 \"M.a\"", "1030::nmadd -f -s 20 \"M.c\"",]
+        );
+    }
+
+    #[test]
+    fn text_coverage_of_sparse_and_unsized() {
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0x1000);
+        elf.func(text, "foo", 0, 0x40);
+        elf.object(text, "kTable", 0x100, 0x100);
+        let obj = process(&elf, 0, &Options::default()).unwrap();
+        assert_eq!(obj.text_coverage(), Some(0.015625));
+
+        // With no functions at all, none of .text is covered
+        let mut elf = ElfBuilder::new();
+        elf.code(".text", 0, 0x1000);
+        let obj = process(&elf, 0, &Options::default()).unwrap();
+        assert_eq!(obj.text_coverage(), Some(0.0));
+
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0, 0);
+        elf.func(text, "foo", 0, 0);
+        let obj = process(&elf, 0, &Options::default()).unwrap();
+        assert_eq!(obj.text_coverage(), None);
+
+        // Neither images nor PDBs record the sizes of their symbols
+        let opts = Options::default();
+        let mut image = pe::ImageSyms::new();
+        image.add_section(".text", 0, 0x1000, 0x100, u64::MAX).unwrap();
+        image.add_sym(0, 0x1010, "foo", true, &opts);
+        let obj = image.finish().unwrap();
+        assert_eq!(obj.text_coverage(), None);
+        let pdb = testdata("pdb/Foo.pdb");
+        let obj = pdb::process_pdb(&pdb, 0, &opts).unwrap();
+        assert!(!obj.text().syms.is_empty());
+        assert_eq!(obj.text_coverage(), None);
+    }
+
+    #[test]
+    fn init_array_entries_named_after_targets() {
+        use crate::testelf::Sect;
+        use goblin::elf::section_header::SHF_WRITE;
+        let mut elf = ElfBuilder::new();
+        let text = elf.code(".text", 0x1000, 0x100);
+        elf.func(text, "ctor", 0x1010, 0x10);
+        // A second slot points to nothing with a symbol
+        let mut data = 0x1010u64.to_le_bytes().to_vec();
+        data.extend_from_slice(&0x1080u64.to_le_bytes());
+        elf.add_section(Sect {
+            name: ".init_array".to_string(),
+            sh_type: SHT_INIT_ARRAY,
+            flags: u64::from(SHF_ALLOC | SHF_WRITE),
+            addr: 0x2000,
+            data,
+            size: None,
+        });
+        let opts = Options { ctors: true, ..Default::default() };
+        let obj = process(&elf, 0x10000, &opts).unwrap();
+        let init = &obj.sections[1];
+        assert_eq!(init.name, ".init_array");
+        assert_eq!(syms(init), [(0x12000, "init_array_ctor", 8)]);
+        assert!(!init.syms[0].is_func);
+
+        let obj = process(&elf, 0x10000, &Options::default()).unwrap();
+        let names = obj.sections.iter().flat_map(|s| &s.syms).map(|r| &r.name);
+        assert!(names.eq(["ctor"].iter()));
+    }
+}
@@ -1,158 +1,2382 @@
-use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::ffi::{OsStr, OsString};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufWriter, Error, ErrorKind, Result, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-extern crate goblin;
-extern crate memmap;
+extern crate glob;
+extern crate mdb_edk2;
 extern crate pico_args;
 
-fn parse_args() -> Option<(PathBuf, PathBuf)> {
-    let mut args = pico_args::Arguments::from_env();
+use mdb_edk2::config::{self, Value};
+use mdb_edk2::debuglog::{Event, Module, Moved};
+use mdb_edk2::objdir::ObjDir;
+use mdb_edk2::pdb::process_pdb;
+use mdb_edk2::pe::{efi_build_id, process_efi, section_rvas};
+use mdb_edk2::{
+    build_id, debuglog, follow_debuglink, format_gdb, format_json,
+    format_nmadd, format_nmdel, format_table, inspect_file, process_file,
+    process_file_rebased, FmtOpts, Kind, Options, Radix, Section, SymRes,
+};
 
-    let obj_path: PathBuf = args.value_from_str("-d").ok()?;
-    let dbg_output: PathBuf = args.free_from_str().ok()??;
-    Some((dbg_output, obj_path))
+/// How often a followed log is checked for new lines
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Style of the generated commands
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    /// mdb ::nmadd commands
+    Nmadd,
+    /// GDB add-symbol-file commands
+    Gdb,
+    /// JSON array of symbol descriptions
+    Json,
+    /// Table of the symbols, for reading
+    Table,
 }
 
-fn usage() -> ! {
-    println!("usage: mdb-tianocore -d <obj path> <debug output file>");
-    std::process::exit(0);
-}
-
-struct SymRes<'a> {
-    name: &'a str,
-    size: u64,
-    is_func: bool,
-}
-
-fn post_process(results: &BTreeMap<u64, SymRes>, base: &str, addr_end: u64) {
-    let mut iter = results.iter().peekable();
-    while let Some((addr, res)) = iter.next() {
-        let size = match res.size {
-            0 => {
-                // For any entries which lack a proper size, stretch it out
-                // until it hits the next entry (or the end of the section).
-                if let Some((naddr, _)) = iter.peek() {
-                    *naddr - addr
-                } else {
-                    addr_end - addr
+impl std::str::FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "nmadd" => Ok(Format::Nmadd),
+            "gdb" => Ok(Format::Gdb),
+            "json" => Ok(Format::Json),
+            "table" => Ok(Format::Table),
+            _ => Err("unknown format"),
+        }
+    }
+}
+
+/// Format of the --dump-map output
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MapFormat {
+    /// JSON array with an object for each module
+    Json,
+    /// Tab-separated values, with a heading line
+    Tsv,
+}
+
+impl std::str::FromStr for MapFormat {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(MapFormat::Json),
+            "tsv" => Ok(MapFormat::Tsv),
+            _ => Err("unknown map format"),
+        }
+    }
+}
+
+/// Order of the output
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sort {
+    /// By address, of the modules and their symbols
+    Addr,
+    /// By name, for diffing the output of different builds
+    Name,
+}
+
+impl std::str::FromStr for Sort {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "addr" => Ok(Sort::Addr),
+            "name" => Ok(Sort::Name),
+            _ => Err("unknown sort order"),
+        }
+    }
+}
+
+struct Args {
+    dbg_outputs: Vec<PathBuf>,
+    addrmap: Option<PathBuf>,
+    /// The object, load address and module name given with --single, in
+    /// place of the modules of a log
+    single: Option<(PathBuf, u64, String)>,
+    obj_paths: Vec<PathBuf>,
+    list: bool,
+    dump_map: Option<MapFormat>,
+    disambiguate: bool,
+    require_started: bool,
+    obj_ext: String,
+    map_path: Option<PathBuf>,
+    recursive: bool,
+    efi_fallback: bool,
+    pdb: bool,
+    follow_debuglink: bool,
+    base_from_efi: bool,
+    emit_entry: bool,
+    emit_bounds: bool,
+    guid_alias: bool,
+    summary: bool,
+    quiet: bool,
+    fail_fast: bool,
+    stream: bool,
+    follow: bool,
+    header: bool,
+    timing: bool,
+    split_dir: Option<PathBuf>,
+    split_empty: bool,
+    modules: Vec<glob::Pattern>,
+    out_path: Option<PathBuf>,
+    cleanup: Option<PathBuf>,
+    format: Format,
+    sort: Sort,
+    unload: bool,
+    jobs: usize,
+    limit: Option<usize>,
+    min_coverage: Option<f64>,
+    offset: u64,
+    relative_to: Option<String>,
+    /// The load address of the --relative-to module, subtracted from every
+    /// emitted address
+    rebase: u64,
+    addr_radix: Radix,
+    cache_dir: Option<PathBuf>,
+    opts: Options,
+    fopts: FmtOpts,
+}
+
+const USAGE: &str = "\
+usage: mdb-tianocore [options] -d <obj path> <debug output file | ->...
+       mdb-tianocore --list [options] <debug output file | ->...
+       mdb-tianocore [options] -d <obj path> --addrmap <file>
+       mdb-tianocore [options] --single <obj file> --at <hex> [--name <name>]
+       mdb-tianocore --inspect <obj file>
+
+Generate mdb ::nmadd commands for the modules loaded in an EDK2 debug log.
+
+The debug output file is the captured firmware console log, which may be
+gzip-compressed, or zstd-compressed; use `-` to read it from stdin.  zstd
+input is decompressed by running the external `zstd` command, which must be
+installed and on the PATH.  For each module loaded at runtime, the symbols
+are read from the matching `<module>.debug` ELF object in the obj path.  A
+module reloaded at a different address is taken to be at the latest one.
+Given several logs, the modules loaded in each are combined likewise, with a
+later load at the same address, or of the same module, replacing an earlier
+one.
+
+Defaults for the options may be given in mdb-edk2.toml, in the working
+directory, or in the file named with --config, with a `<key> = <value>` line
+for each.  The keys are the long option names, without the leading `--`,
+plus obj-path for -d and output for -o.  Options without a value take true
+or false (or a count, for -v); those with one take a string or number, or
+an array of them for those which may be repeated, as in:
+
+    obj-path = [\"Build/OvmfX64/DEBUG_GCC5/X64\"]
+    format = \"gdb\"
+    demangle = true
+
+When not given on the command line, the obj path is taken from
+$MDB_EDK2_OBJDIR, and the debug output file from $MDB_EDK2_LOG.  Options
+given on the command line take precedence over the config file, which in
+turn takes precedence over these variables.  Large objects are mapped into
+memory, unless $MDB_EDK2_NO_MMAP is set.
+
+Exits with status 2 if the log shows no modules being loaded, or 1 if none
+of the loaded modules could be processed (with --strict, if any could not
+be).
+
+options:
+    -d <obj path>       directory containing the <module>.debug objects; may
+                        be repeated, or given as a `:`-separated list, to
+                        search several directories in order
+    --config <file>     read option defaults from <file>, rather than from
+                        mdb-edk2.toml in the working directory
+    --addrmap <file>    read the modules loaded from <file>, each line
+                        holding a module name and its hex load address,
+                        making the debug output optional (loads found in
+                        any debug output given take precedence)
+    --single <obj>      process only the object <obj>, as the module loaded
+                        at the address given with --at <hex>, rather than
+                        those in a log; it is named for the file, without
+                        its extension, unless given --name <name>
+    --list              list the loaded modules, by address, without reading
+                        any objects
+    --dump-map <fmt>    write every loaded module, with its address, entry
+                        point, whether it was started, and GUID, as json or
+                        tsv for other tools, without reading any objects
+    --disambiguate      take a module loaded at several addresses to be as
+                        many distinct images, rather than to have moved,
+                        suffixing each with its address, as <module>_<addr>
+    --obj-ext <ext>     extension of the object files (default: debug)
+    --map <file>        read explicit object paths from <file>, each line
+                        holding a module name and its object path,
+                        relative to the first obj path, if any
+    --recursive         search for objects throughout the obj path,
+                        rather than only at its top level
+    --module <glob>     only process modules whose name matches <glob>;
+                        may be repeated
+    --require-started   only process modules which the log shows to have
+                        started, with an `Image at <addr> started` line
+    --efi-fallback      for modules without an object file, use the symbols
+                        retained in the <module>.efi image
+    --pdb               read the symbols of each module from <module>.pdb,
+                        as built by the Visual Studio toolchain, rather
+                        than from an ELF object
+    --follow-debuglink  for objects stripped of their symbols, read those of
+                        the file in the obj path named by .gnu_debuglink
+    --base-from-efi     place sections at the RVAs found in the matching
+                        <module>.efi image, rather than trusting the
+                        addresses in the object file
+    -o <out file>       write commands to <out file> instead of stdout
+    --split-dir <dir>   write the commands for each module to its own file,
+                        <dir>/<module>.mdb (or .gdb, .json, .txt), rather
+                        than to stdout
+    --split-empty       write files for modules without symbols, which are
+                        otherwise skipped by --split-dir
+    --format <fmt>      style of the generated commands:
+                          nmadd   mdb ::nmadd commands (default)
+                          gdb     GDB add-symbol-file commands
+                          json    JSON array describing each symbol
+                          table   columns of address, size, kind (F or O),
+                                  module and name, for reading
+    --sort <order>      order the modules, and the symbols of each, by addr
+                        (default) or name, the latter for diffing the
+                        output of different builds
+    --unload            emit commands removing the symbols which would
+                        otherwise be added
+    --cleanup <file>    also write to <file> the commands removing every
+                        symbol added, as with --unload, to be run once
+                        done debugging
+    --strict            exit with status 1 if any module could not be
+                        processed, and skip modules whose symbols extend
+                        beyond their .text section, or whose object and
+                        .efi image have different build-ids
+    --header            start the output with comments recording the version,
+                        inputs and time of its generation
+    --timing            print the time taken to read the logs, and to
+                        process each module and all of them, to stderr
+    --fail-fast         stop with status 1 at the first module which cannot
+                        be processed, rather than carrying on; modules
+                        already under way in parallel are finished, but
+                        only the output preceding the failure is written
+    -q, --quiet         do not report the errors for each module which could
+                        not be processed (the exit status still reflects
+                        them)
+    --summary           print the number of symbols found for each module,
+                        and in total, to stderr
+    --offset <hex>      add <hex> to every emitted address (other than the
+                        absolute values of --abs), for a debugger view
+                        shifted relative to the log
+    --relative-to <module>
+                        subtract the load address of <module> from every
+                        emitted address, to compare runs in which all the
+                        modules have shifted; modules loaded below it are
+                        not processed
+    --dec-addr          read addresses in the log as decimal, unless prefixed
+                        by 0x, for platforms which print them so
+    --cache-dir <dir>   cache the output for each module in <dir>, reusing
+                        it while the object file is unchanged
+    --stream            emit the output for each module as its load is read,
+                        rather than once the logs are read in full, in
+                        address order
+    --follow            like --stream, but keep waiting for the log to grow,
+                        as with `tail -f`; with --unload, symbols are added
+                        as modules load and removed as they unload
+    --jobs <n>          process at most <n> objects in parallel (defaults
+                        to the number of CPUs)
+    --data              include symbols from data sections (.data, .bss,
+                        .rodata, ...), not just .text
+    --locals            include local symbols such as static variables,
+                        suffixing repeated names to keep them unique
+    --aliases           emit every name found at an address, rather than
+                        only the preferred one
+    --asm-as-func       emit untyped global symbols in code sections, as
+                        defined in assembly, as functions rather than objects
+    --ctors             include the constructor and destructor arrays,
+                        .init_array, .fini_array, .ctors and so on, naming
+                        each entry <section>_<function> after the function
+                        it points to; entries pointing to functions without
+                        symbols, or not yet relocated, are left unnamed
+    --no-weak           omit weak symbols, which may be overridden by a
+                        definition in another object
+    --abs               include symbols with absolute values (SHN_ABS), such
+                        as register bases, as objects at those values;
+                        being in no section, they may well not be addresses,
+                        so use with care; nor are they moved by --offset or
+                        --relative-to
+    --abs-relative      like --abs, but treat each value as an offset from
+                        the load address, moving along with it
+    --source            note the source file and line of each function, from
+                        the DWARF line information, in a comment following
+                        its ::nmadd command
+    --demangle          demangle Rust and C++ symbol names
+    --keep-version      keep the @VERSION suffix of versioned symbol names,
+                        which is otherwise removed
+    --emit-entry        add a <module>_entry symbol at each logged entry
+                        point not already covered by a symbol
+    --emit-bounds       add zero-size <module>_text_start and _text_end
+                        markers at the bounds of .text (and of the other
+                        sections output with --data)
+    --guid-alias        add a zero-size symbol named after the file GUID of
+                        each module whose load line gives one, at its entry
+                        point (or load address)
+    --kind <kind>       only emit symbols of the given kind: func, obj, or
+                        all (default)
+    --no-stretch        emit unsized symbols with a size of 0, rather than
+                        extending each to the next symbol
+    --limit <n>         emit only the <n> largest symbols of each module, for
+                        a quick look at the most significant; the rest are
+                        lost, and the sizes are those before the cut.  The
+                        symbols of --emit-entry and --emit-bounds are not
+                        counted
+    --min-coverage <percent>
+                        warn of modules whose functions, by their own
+                        sizes, cover less than <percent> of .text, as is
+                        typical of stripped or mismatched objects; objects
+                        whose functions are all unsized are not checked
+    --min-size <bytes>  omit symbols smaller than <bytes>, after sizing
+                        unsized symbols by the distance to the next one
+    --radix <radix>     write ::nmadd sizes in hex (default) or dec, the
+                        latter with mdb's 0t prefix
+    --use-end           give each symbol's extent as its end address, with
+                        ::nmadd -e <end>, rather than its size (-s <size>),
+                        for versions of mdb preferring that form
+    --qualify-section   include the section in each name, as in
+                        <module>.text.<symbol>
+    --only <file>       only emit symbols whose names match one of the
+                        patterns (exact names or globs) listed one per
+                        line in <file>
+    --exclude <file>    omit symbols whose names match one of the patterns
+                        in <file>, even if also matched by --only
+    --strip-underscore  remove a leading `_` from each symbol name, as added
+                        by some toolchains
+    --prefix <str>      name each module <str>_<module>, to tell apart the
+                        symbols of different builds (<str> may not contain
+                        the delimiter)
+    --delimiter <c>     separate module and symbol names with <c> rather
+                        than `.` (whitespace and quotes are not allowed)
+    --inspect <obj>     describe the sections of the ELF object <obj>, and
+                        the number of symbols in each, then exit
+    -v, --verbose       log the progress of each module to stderr; repeat
+                        (or use -vv) to include the section headers
+    -h, --help          print this help and exit
+    -V, --version       print the version and exit
+";
+
+/// Environment variables standing in for `-d` and the debug output file,
+/// when they are not given on the command line
+const OBJDIR_VAR: &str = "MDB_EDK2_OBJDIR";
+const LOG_VAR: &str = "MDB_EDK2_LOG";
+
+/// The path held by the environment variable `var`, if set and not empty
+fn env_path(var: &str) -> Option<PathBuf> {
+    std::env::var_os(var).filter(|val| !val.is_empty()).map(PathBuf::from)
+}
+
+/// Split a `:`-separated list of directories
+fn split_dirs(dirs: &OsStr) -> std::result::Result<Vec<PathBuf>, Infallible> {
+    Ok(std::env::split_paths(dirs)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .collect())
+}
+
+/// The configuration file read from the working directory, unless another
+/// is named with --config
+const CONFIG_FILE: &str = "mdb-edk2.toml";
+
+/// The options which may be given defaults in the configuration file: the
+/// key for each, its flags, the first of which is passed on, and whether it
+/// takes a value.  The keys are the long option names, plus obj-path for -d
+/// and output for -o.
+const CONFIG_OPTIONS: &[(&str, &[&str], bool)] = &[
+    ("obj-path", &["-d"], true),
+    ("addrmap", &["--addrmap"], true),
+    ("single", &["--single"], true),
+    ("list", &["--list"], false),
+    ("dump-map", &["--dump-map"], true),
+    ("disambiguate", &["--disambiguate"], false),
+    ("obj-ext", &["--obj-ext"], true),
+    ("map", &["--map"], true),
+    ("recursive", &["--recursive"], false),
+    ("module", &["--module"], true),
+    ("require-started", &["--require-started"], false),
+    ("efi-fallback", &["--efi-fallback"], false),
+    ("pdb", &["--pdb"], false),
+    ("follow-debuglink", &["--follow-debuglink"], false),
+    ("base-from-efi", &["--base-from-efi"], false),
+    ("output", &["-o"], true),
+    ("split-dir", &["--split-dir"], true),
+    ("split-empty", &["--split-empty"], false),
+    ("format", &["--format"], true),
+    ("sort", &["--sort"], true),
+    ("unload", &["--unload"], false),
+    ("cleanup", &["--cleanup"], true),
+    ("strict", &["--strict"], false),
+    ("header", &["--header"], false),
+    ("timing", &["--timing"], false),
+    ("fail-fast", &["--fail-fast"], false),
+    ("quiet", &["-q", "--quiet"], false),
+    ("summary", &["--summary"], false),
+    ("offset", &["--offset"], true),
+    ("relative-to", &["--relative-to"], true),
+    ("dec-addr", &["--dec-addr"], false),
+    ("cache-dir", &["--cache-dir"], true),
+    ("stream", &["--stream"], false),
+    ("follow", &["--follow"], false),
+    ("jobs", &["--jobs"], true),
+    ("data", &["--data"], false),
+    ("locals", &["--locals"], false),
+    ("aliases", &["--aliases"], false),
+    ("asm-as-func", &["--asm-as-func"], false),
+    ("ctors", &["--ctors"], false),
+    ("no-weak", &["--no-weak"], false),
+    ("abs", &["--abs"], false),
+    ("abs-relative", &["--abs-relative"], false),
+    ("source", &["--source"], false),
+    ("demangle", &["--demangle"], false),
+    ("keep-version", &["--keep-version"], false),
+    ("emit-entry", &["--emit-entry"], false),
+    ("emit-bounds", &["--emit-bounds"], false),
+    ("guid-alias", &["--guid-alias"], false),
+    ("kind", &["--kind"], true),
+    ("no-stretch", &["--no-stretch"], false),
+    ("limit", &["--limit"], true),
+    ("min-coverage", &["--min-coverage"], true),
+    ("min-size", &["--min-size"], true),
+    ("radix", &["--radix"], true),
+    ("use-end", &["--use-end"], false),
+    ("qualify-section", &["--qualify-section"], false),
+    ("only", &["--only"], true),
+    ("exclude", &["--exclude"], true),
+    ("strip-underscore", &["--strip-underscore"], false),
+    ("prefix", &["--prefix"], true),
+    ("delimiter", &["--delimiter"], true),
+    ("verbose", &["-v", "--verbose"], false),
+];
+
+/// Read the option defaults from the configuration file, as arguments to
+/// precede those in `argv`, leaving out any option given in `argv`.  The
+/// file named by --config, which is removed from `argv`, must exist, while
+/// the default one need not.
+fn config_args(argv: &mut Vec<OsString>) -> Result<Vec<OsString>> {
+    let mut path = None;
+    let mut explicit = true;
+    if let Some(idx) = argv.iter().position(|arg| arg == "--config") {
+        argv.remove(idx);
+        if idx == argv.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, "missing file"));
+        }
+        path = Some(PathBuf::from(argv.remove(idx)));
+    } else if let Some(idx) = argv.iter().position(|arg| {
+        arg.to_str().is_some_and(|arg| arg.starts_with("--config="))
+    }) {
+        let arg = argv.remove(idx);
+        path = Some(PathBuf::from(&arg.to_str().unwrap()[9..]));
+    }
+    let path = path.unwrap_or_else(|| {
+        explicit = false;
+        PathBuf::from(CONFIG_FILE)
+    });
+    let in_file =
+        |e: Error| Error::new(e.kind(), format!("{}: {}", path.display(), e));
+    let text = match std::fs::read_to_string(&path) {
+        Err(e) if e.kind() == ErrorKind::NotFound && !explicit => {
+            return Ok(Vec::new());
+        }
+        res => res.map_err(in_file)?,
+    };
+
+    let given = |name: &str| {
+        argv.iter().filter_map(|arg| arg.to_str()).any(|arg| {
+            arg == name
+                || arg.strip_prefix(name).is_some_and(|v| v.starts_with('='))
+                // -vv being a repeated -v
+                || (name == "-v" && arg == "-vv")
+        })
+    };
+    let mut out = Vec::new();
+    for (key, value) in config::parse(&text).map_err(in_file)? {
+        let invalid = |msg: &str| {
+            let msg = format!("{}: {}", key, msg);
+            in_file(Error::new(ErrorKind::InvalidData, msg))
+        };
+        let (_, names, valued) = CONFIG_OPTIONS
+            .iter()
+            .find(|(k, _, _)| *k == key)
+            .ok_or_else(|| invalid("unknown option"))?;
+        if names.iter().any(|name| given(name)) {
+            continue;
+        }
+        let flag = OsString::from(names[0]);
+        match (value, valued) {
+            (Value::Bool(set), false) => {
+                out.extend(set.then_some(flag));
+            }
+            // A count, for the options which may be repeated
+            (Value::Int(n), false) if n >= 0 => {
+                out.extend((0..n).map(|_| flag.clone()));
+            }
+            (Value::Array(vals), true) => {
+                for val in vals {
+                    if let Value::Array(_) | Value::Bool(_) = val {
+                        return Err(invalid("expected strings or numbers"));
+                    }
+                    out.push(flag.clone());
+                    out.push(val.to_string().into());
                 }
             }
-            sz => sz,
-        };
-        // While '`' would be the expected delimiter between object and function
-        // name, it (currently) confuses name resolution in mdb-bhyve since
-        // there are effectively no objects.  Use '.' instead, so the private
-        // symbols can be referred to directly.
-        println!(
-            "{:x}::nmadd -{} -s {:x} \"{}.{}\"",
-            addr,
-            if res.is_func { "f" } else { "o" },
-            size,
-            base,
-            res.name
-        );
+            (Value::Bool(_), true) => {
+                return Err(invalid("expected a value, not a boolean"));
+            }
+            (val, true) => {
+                out.push(flag);
+                out.push(val.to_string().into());
+            }
+            (_, false) => return Err(invalid("expected true or false")),
+        }
     }
+    Ok(out)
 }
 
-fn process_file(base: &str, path: &Path, addr_start: u64) -> Result<()> {
-    if !path.metadata()?.is_file() {
-        return Err(Error::new(ErrorKind::InvalidData, "bad object file"));
-    }
-    let map = unsafe { memmap::Mmap::map(&File::open(path)?)? };
-    let elf = goblin::elf::Elf::parse(&map)
-        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+/// Parse the command line arguments `argv`, following the program name
+fn parse_args(mut argv: Vec<OsString>) -> Option<Args> {
+    let mut defaults = config_args(&mut argv).unwrap_or_else(|e| {
+        eprintln!("Error reading config: {}", e);
+        std::process::exit(1);
+    });
+    defaults.append(&mut argv);
+    let mut args = pico_args::Arguments::from_vec(defaults);
 
-    let (text_shndx, addr_end) = if let Some((ndx, hdr)) =
-        elf.section_headers.iter().enumerate().find(|(_ndx, hdr)| {
-            if let Some(Ok(shdr_name)) = elf.shdr_strtab.get(hdr.sh_name) {
-                shdr_name == ".text"
-            } else {
-                false
+    if args.contains(["-h", "--help"]) {
+        print!("{}", USAGE);
+        std::process::exit(0);
+    }
+    if args.contains(["-V", "--version"]) {
+        println!("mdb-tianocore {}", env!("CARGO_PKG_VERSION"));
+        std::process::exit(0);
+    }
+    let inspect: Option<PathBuf> = args.opt_value_from_str("--inspect").ok()?;
+    if let Some(path) = inspect {
+        match inspect_file(&path) {
+            Ok(desc) => print!("{}", desc),
+            Err(e) => {
+                eprintln!("Error inspecting {}: {}", path.display(), e);
+                std::process::exit(1);
             }
-        }) {
-        (ndx, addr_start + hdr.sh_size)
-    } else {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            "No .text section found",
-        ));
+        }
+        std::process::exit(0);
+    }
+
+    // Listing the modules needs no objects
+    let dump_map: Option<MapFormat> =
+        args.opt_value_from_str("--dump-map").ok()?;
+    let list = args.contains("--list") || dump_map.is_some();
+    let single: Option<PathBuf> = args.opt_value_from_str("--single").ok()?;
+    let at: Option<u64> = args.opt_value_from_fn("--at", parse_hex).ok()?;
+    let name: Option<String> = args.opt_value_from_str("--name").ok()?;
+    let single = match (single, at) {
+        (Some(obj), Some(at)) if !list => {
+            let name = match name {
+                Some(name) => name,
+                None => obj.file_stem()?.to_str()?.to_string(),
+            };
+            Some((obj, at, name))
+        }
+        (None, None) if name.is_none() => None,
+        _ => return None,
     };
+    let obj_paths: Vec<Vec<PathBuf>> =
+        args.values_from_os_str("-d", split_dirs).ok()?;
+    let mut obj_paths: Vec<PathBuf> = obj_paths.into_iter().flatten().collect();
+    if obj_paths.is_empty() {
+        if let Some(dirs) = std::env::var_os(OBJDIR_VAR) {
+            obj_paths = split_dirs(&dirs).unwrap();
+        }
+    }
+    if obj_paths.is_empty() && !list && single.is_none() {
+        return None;
+    }
+    let out_path: Option<PathBuf> = args.opt_value_from_str("-o").ok()?;
+    let map_path: Option<PathBuf> = args.opt_value_from_str("--map").ok()?;
+    let cache_dir: Option<PathBuf> =
+        args.opt_value_from_str("--cache-dir").ok()?;
+    let obj_ext: String = args
+        .opt_value_from_str("--obj-ext")
+        .ok()?
+        .unwrap_or_else(|| "debug".to_string());
+    let obj_ext = obj_ext.trim_start_matches('.').to_string();
+    let recursive = args.contains("--recursive");
+    let efi_fallback = args.contains("--efi-fallback");
+    let follow_debuglink = args.contains("--follow-debuglink");
+    let base_from_efi = args.contains("--base-from-efi");
+    let pdb = args.contains("--pdb");
+    if pdb && (base_from_efi || follow_debuglink) {
+        return None;
+    }
+    let emit_entry = args.contains("--emit-entry");
+    let emit_bounds = args.contains("--emit-bounds");
+    let guid_alias = args.contains("--guid-alias");
+    let summary = args.contains("--summary");
+    let quiet = args.contains(["-q", "--quiet"]);
+    let fail_fast = args.contains("--fail-fast");
+    let follow = args.contains("--follow");
+    let header = args.contains("--header");
+    let timing = args.contains("--timing");
+    let split_dir: Option<PathBuf> =
+        args.opt_value_from_str("--split-dir").ok()?;
+    let split_empty = args.contains("--split-empty");
+    if split_dir.is_some() && out_path.is_some() {
+        return None;
+    }
+    let stream = args.contains("--stream") || follow;
+    if stream && single.is_some() {
+        return None;
+    }
+    let disambiguate = args.contains("--disambiguate");
+    let require_started = args.contains("--require-started");
+    let relative_to: Option<String> =
+        args.opt_value_from_str("--relative-to").ok()?;
+    // Streaming emits each module as it loads, before any later load, start
+    // or reference module is known
+    if (disambiguate || require_started || relative_to.is_some()) && stream {
+        return None;
+    }
+    let modules = args.values_from_str("--module").ok()?;
+    let mut verbose = 0;
+    while args.contains(["-v", "--verbose"]) {
+        verbose += 1;
+    }
+    while args.contains("-vv") {
+        verbose += 2;
+    }
+    let abs_relocate = args.contains("--abs-relative");
+    let opts = Options {
+        data: args.contains("--data"),
+        locals: args.contains("--locals"),
+        demangle: args.contains("--demangle"),
+        aliases: args.contains("--aliases"),
+        verbose,
+        strict: args.contains("--strict"),
+        keep_version: args.contains("--keep-version"),
+        abs: args.contains("--abs") || abs_relocate,
+        abs_relocate,
+        source: args.contains("--source"),
+        asm_as_func: args.contains("--asm-as-func"),
+        no_weak: args.contains("--no-weak"),
+        ctors: args.contains("--ctors"),
+    };
+    let format =
+        args.opt_value_from_str("--format").ok()?.unwrap_or(Format::Nmadd);
+    let sort = args.opt_value_from_str("--sort").ok()?.unwrap_or(Sort::Addr);
+    let unload = args.contains("--unload");
+    if unload && matches!(format, Format::Json | Format::Table) {
+        return None;
+    }
+    let cleanup: Option<PathBuf> = args.opt_value_from_str("--cleanup").ok()?;
+    if cleanup.is_some()
+        && (unload || matches!(format, Format::Json | Format::Table))
+    {
+        return None;
+    }
+    // A followed log never ends, leaving nowhere to close the JSON array,
+    // and JSON has no comments in which to put a header.
+    if (follow || header) && format == Format::Json {
+        return None;
+    }
+    let offset =
+        args.opt_value_from_fn("--offset", parse_hex).ok()?.unwrap_or(0);
+    let addr_radix = match args.contains("--dec-addr") {
+        true => Radix::Dec,
+        false => Radix::Hex,
+    };
+    let limit: Option<usize> = args.opt_value_from_str("--limit").ok()?;
+    let min_coverage: Option<f64> =
+        args.opt_value_from_str("--min-coverage").ok()?;
+    if min_coverage.is_some_and(|min| !(0.0..=100.0).contains(&min)) {
+        return None;
+    }
+    let jobs = match args.opt_value_from_str("--jobs").ok()? {
+        Some(0) => return None,
+        Some(n) => n,
+        None => std::thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    let mut fopts = FmtOpts {
+        qualify_section: args.contains("--qualify-section"),
+        strip_underscore: args.contains("--strip-underscore"),
+        no_stretch: args.contains("--no-stretch"),
+        use_end: args.contains("--use-end"),
+        radix: args.opt_value_from_str("--radix").ok()?.unwrap_or(Radix::Hex),
+        kind: args.opt_value_from_str("--kind").ok()?.unwrap_or(Kind::All),
+        ..FmtOpts::default()
+    };
+    if let Some(min_size) = args.opt_value_from_str("--min-size").ok()? {
+        fopts.min_size = min_size;
+    }
+    if let Some(delim) = args.opt_value_from_str("--delimiter").ok()? {
+        if !FmtOpts::valid_delim(delim) {
+            return None;
+        }
+        fopts.delim = delim;
+    }
+    let prefix: Option<String> = args.opt_value_from_str("--prefix").ok()?;
+    if let Some(prefix) = prefix {
+        if !fopts.valid_prefix(&prefix) {
+            return None;
+        }
+        fopts.prefix = Some(prefix);
+    }
+    let only: Option<PathBuf> = args.opt_value_from_str("--only").ok()?;
+    fopts.only = only.map(|path| load_patterns(&path));
+    let exclude: Option<PathBuf> = args.opt_value_from_str("--exclude").ok()?;
+    if let Some(path) = exclude {
+        fopts.exclude = load_patterns(&path);
+    }
+    let addrmap: Option<PathBuf> = args.opt_value_from_str("--addrmap").ok()?;
+    if addrmap.is_some() && (stream || single.is_some()) {
+        return None;
+    }
+    let mut dbg_outputs: Vec<PathBuf> =
+        args.free_os().ok()?.into_iter().map(PathBuf::from).collect();
+    if single.is_some() {
+        if !dbg_outputs.is_empty() {
+            return None;
+        }
+    } else if dbg_outputs.is_empty() && addrmap.is_none() {
+        dbg_outputs.extend(env_path(LOG_VAR));
+        if dbg_outputs.is_empty() {
+            return None;
+        }
+    }
+    if follow && dbg_outputs.len() > 1 {
+        return None;
+    }
+    Some(Args {
+        dbg_outputs,
+        addrmap,
+        single,
+        obj_paths,
+        list,
+        dump_map,
+        disambiguate,
+        require_started,
+        obj_ext,
+        map_path,
+        recursive,
+        efi_fallback,
+        pdb,
+        follow_debuglink,
+        base_from_efi,
+        emit_entry,
+        emit_bounds,
+        guid_alias,
+        summary,
+        quiet,
+        fail_fast,
+        stream,
+        follow,
+        header,
+        timing,
+        split_dir,
+        split_empty,
+        modules,
+        out_path,
+        cleanup,
+        format,
+        sort,
+        unload,
+        jobs,
+        limit,
+        min_coverage,
+        offset,
+        relative_to,
+        rebase: 0,
+        addr_radix,
+        cache_dir,
+        opts,
+        fopts,
+    })
+}
 
-    let mut results = BTreeMap::new();
+/// Read the symbol name patterns listed one per line in the file at `path`,
+/// skipping blank lines and those starting with `#`, exiting with an error
+/// if it cannot be read.
+fn load_patterns(path: &Path) -> Vec<glob::Pattern> {
+    let fail = |why: &dyn std::fmt::Display| -> ! {
+        eprintln!("Error reading patterns {}: {}", path.display(), why);
+        std::process::exit(1);
+    };
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| fail(&e));
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| glob::Pattern::new(line).unwrap_or_else(|e| fail(&e)))
+        .collect()
+}
 
-    for sym in elf.syms.iter() {
-        if sym.st_shndx != text_shndx {
-            continue;
+impl Args {
+    /// The files from which the module loads are read
+    fn inputs(&self) -> Vec<&Path> {
+        self.addrmap
+            .iter()
+            .chain(self.dbg_outputs.iter())
+            .map(PathBuf::as_path)
+            .collect()
+    }
+
+    /// The address to emit for the logged address `addr`, as shifted by
+    /// --offset and --relative-to
+    fn shift(&self, addr: u64) -> Option<u64> {
+        addr.checked_add(self.offset)?.checked_sub(self.rebase)
+    }
+}
+
+/// Parse a hex argument, with or without a 0x prefix
+fn parse_hex(s: &str) -> std::result::Result<u64, std::num::ParseIntError> {
+    u64::from_str_radix(s.strip_prefix("0x").unwrap_or(s), 16)
+}
+
+fn usage() -> ! {
+    eprint!("{}", USAGE);
+    std::process::exit(1);
+}
+
+/// Check that the obj path at `path` is a directory, exiting with an error
+/// naming it otherwise, and return its canonical form.
+fn check_obj_path(path: &Path) -> PathBuf {
+    let fail = |why: &dyn std::fmt::Display| -> ! {
+        eprintln!("Bad obj path {}: {}", path.display(), why);
+        std::process::exit(1);
+    };
+    match path.metadata() {
+        Ok(meta) if meta.is_dir() => {}
+        Ok(_) => fail(&"not a directory"),
+        Err(e) if e.kind() == ErrorKind::NotFound => fail(&"does not exist"),
+        Err(e) => fail(&e),
+    }
+    path.canonicalize().unwrap_or_else(|e| fail(&e))
+}
+
+/// The generated output for a module
+struct ModOutput {
+    lines: Vec<String>,
+    /// The commands removing the symbols added by `lines`, for --cleanup
+    cleanup: Vec<String>,
+    /// Problems found with the object, to be reported however the output
+    /// was come by, cached or not
+    warnings: Vec<String>,
+    funcs: usize,
+    objs: usize,
+}
+
+/// The files from which the symbols of a module are read
+#[derive(Debug)]
+enum Source {
+    /// An ELF object
+    Obj(PathBuf),
+    /// An ELF object, with its sections placed as in a PE image
+    Rebased(PathBuf, PathBuf),
+    /// A PE image
+    Efi(PathBuf),
+    /// A PDB, from the Visual Studio toolchain
+    Pdb(PathBuf),
+}
+
+impl Source {
+    /// The file providing the symbols
+    fn path(&self) -> &Path {
+        match self {
+            Source::Obj(path)
+            | Source::Rebased(path, _)
+            | Source::Efi(path)
+            | Source::Pdb(path) => path,
         }
+    }
 
-        if sym.is_function() {
-            if let Some(Ok(name)) = elf.strtab.get(sym.st_name) {
-                results.insert(
-                    addr_start + sym.st_value,
-                    SymRes { name, size: sym.st_size, is_func: true },
-                );
-            }
-        } else if sym.st_bind() == goblin::elf::sym::STB_GLOBAL {
-            // Functions implemented in assembly may not be properly typed
-            if let Some(Ok(name)) = elf.strtab.get(sym.st_name) {
-                results.insert(
-                    addr_start + sym.st_value,
-                    SymRes { name, size: sym.st_size, is_func: false },
+    /// Every file read
+    fn inputs(&self) -> Vec<&Path> {
+        match self {
+            Source::Rebased(obj, efi) => vec![obj, efi],
+            _ => vec![self.path()],
+        }
+    }
+}
+
+/// The object to read in place of `obj`, which is the file named by its
+/// `.gnu_debuglink` if it has been stripped of its symbols
+fn debuglinked(args: &Args, obj: PathBuf) -> Result<PathBuf> {
+    match follow_debuglink(&obj, &args.obj_paths)? {
+        Some(linked) => {
+            if args.opts.verbose >= 1 {
+                eprintln!(
+                    "{}: following .gnu_debuglink to {}",
+                    obj.display(),
+                    linked.display()
                 );
             }
+            Ok(linked)
         }
+        None => Ok(obj),
     }
-    post_process(&results, base, addr_end);
-    Ok(())
 }
 
-fn main() {
-    let (dbg, obj_dir) = parse_args().unwrap_or_else(|| usage());
+/// Locate the files from which to read the symbols of module `base`, along
+/// with the image whose build-id an object should match, if there is one.
+fn find_source(
+    args: &Args,
+    objs: &ObjDir,
+    base: &str,
+) -> Result<(Source, Option<PathBuf>)> {
+    let found = match (&args.single, args.pdb) {
+        (Some((obj, _, _)), _) => Ok(obj.clone()),
+        (None, true) => objs.find_ext(base, "pdb"),
+        (None, false) => objs.find(base),
+    };
+    let src = match found {
+        Ok(pdb) if args.pdb => Source::Pdb(pdb),
+        Ok(obj) if args.base_from_efi => {
+            Source::Rebased(obj, objs.find_ext(base, "efi")?)
+        }
+        Ok(obj) => Source::Obj(obj),
+        Err(e) if args.efi_fallback && e.kind() == ErrorKind::NotFound => {
+            Source::Efi(objs.find_ext(base, "efi").map_err(|_| e)?)
+        }
+        Err(e) => return Err(e),
+    };
+    let src = match src {
+        Source::Obj(obj) if args.follow_debuglink => {
+            Source::Obj(debuglinked(args, obj)?)
+        }
+        Source::Rebased(obj, efi) if args.follow_debuglink => {
+            Source::Rebased(debuglinked(args, obj)?, efi)
+        }
+        src => src,
+    };
+    if args.opts.verbose >= 1 {
+        eprintln!("{}: using {}", base, src.path().display());
+    }
+    let efi = match &src {
+        Source::Obj(_) => objs.find_ext(base, "efi").ok(),
+        Source::Rebased(_, efi) => Some(efi.clone()),
+        Source::Efi(_) | Source::Pdb(_) => None,
+    };
+    Ok((src, efi))
+}
+
+/// Describe the mismatch, if the object `obj` and the image `efi` both record
+/// a build-id and they differ, which indicates that the object is stale.
+fn stale_build_id(obj: &Path, efi: &Path) -> Result<Option<String>> {
+    let (obj_id, efi_id) = match (build_id(obj)?, efi_build_id(efi)?) {
+        (Some(obj_id), Some(efi_id)) => (obj_id, efi_id),
+        _ => return Ok(None),
+    };
+    // The image may hold only a prefix of the ID
+    if obj_id.starts_with(&efi_id) || efi_id.starts_with(&obj_id) {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "build-id of {} does not match {}",
+        obj.display(),
+        efi.display()
+    )))
+}
 
-    if !obj_dir.metadata().unwrap_or_else(|_| usage()).is_dir() {
-        usage();
+/// Generate the commands for the module `module` loaded at `addr_offset`,
+/// removing rather than adding its symbols if `unload` is set.
+fn process_module(
+    args: &Args,
+    objs: &ObjDir,
+    addr_offset: u64,
+    module: &Module,
+    unload: bool,
+) -> Result<ModOutput> {
+    let started = Instant::now();
+    let mut res = module_output(args, objs, addr_offset, module, unload, true);
+    if let (Some(_), Ok(output)) = (&args.cleanup, &mut res) {
+        // Enumerated afresh, so as to remove exactly what is added, with
+        // the warnings for the object already given
+        output.cleanup =
+            module_output(args, objs, addr_offset, module, true, false)?.lines;
+    }
+    if args.timing {
+        eprintln!("timing: {}: {:.2?}", module.base, started.elapsed());
     }
+    res
+}
 
-    let fp = File::open(dbg).unwrap();
-    let bufr = BufReader::new(fp);
-    let mut map = BTreeMap::new();
+/// Like [`process_module`], without the --cleanup commands, reporting the
+/// warnings for the object if `report` is set
+fn module_output(
+    args: &Args,
+    objs: &ObjDir,
+    addr_offset: u64,
+    module: &Module,
+    unload: bool,
+    report: bool,
+) -> Result<ModOutput> {
+    // Everything shifts along with the module itself
+    let addr_offset = args.shift(addr_offset).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "load address {:x} is out of range with --offset or \
+                 --relative-to",
+                addr_offset
+            ),
+        )
+    })?;
+    let (src, efi) = find_source(args, objs, &module.base)?;
+    let efi = efi.as_deref();
+    let output = match &args.cache_dir {
+        Some(dir) => {
+            cached_output(args, dir, &src, efi, addr_offset, module, unload)?
+        }
+        None => generate(args, &src, efi, addr_offset, module, unload)?,
+    };
+    if report {
+        for warning in output.warnings.iter() {
+            eprintln!("Warning for {}: {}", module.base, warning);
+        }
+    }
+    Ok(output)
+}
 
-    for line in bufr.lines().map(|l| l.unwrap()) {
-        let fields: Vec<&str> = line.split_whitespace().collect();
-        // Follow along as modules are loaded:
-        // "Loading <something> at 0x<address> EntryPoint=0x<entry> <file>.efi"
-        if let (Some(&"Loading"), Some(&"at"), Some(addr), Some(file)) =
-            (fields.get(0), fields.get(2), fields.get(3), fields.get(5))
-        {
-            if addr.starts_with("0x") && file.ends_with(".efi") {
-                if let Ok(addr_parsed) =
-                    u64::from_str_radix(addr.trim_start_matches("0x"), 16)
-                {
-                    map.insert(
-                        addr_parsed,
-                        file.trim_end_matches(".efi").to_string(),
+/// The output of [`generate`], as cached in `dir`
+fn cached_output(
+    args: &Args,
+    dir: &Path,
+    src: &Source,
+    efi: Option<&Path>,
+    addr_offset: u64,
+    module: &Module,
+    unload: bool,
+) -> Result<ModOutput> {
+    // Output is cached under everything which determines it, along with the
+    // modification time and size of the files read, so that it is
+    // regenerated if any of them change.
+    let mut settings = args.opts.clone();
+    settings.verbose = 0;
+    let key = format!(
+        "{:?} {:?} {:x} {:?} {:?} {:?} {:?} {:?} {} {} {} {} {:?} {:?}",
+        src,
+        efi,
+        addr_offset,
+        module,
+        settings,
+        args.fopts,
+        args.format,
+        args.sort,
+        unload,
+        args.emit_entry,
+        args.emit_bounds,
+        args.guid_alias,
+        args.limit,
+        args.min_coverage
+    );
+    // The image checked against is as much an input as those read
+    let mut inputs = src.inputs();
+    if let Some(efi) = efi.filter(|efi| !inputs.contains(efi)) {
+        inputs.push(efi);
+    }
+    let mut stamp = Vec::new();
+    for input in inputs {
+        let meta = input.metadata()?;
+        let mtime = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        stamp.push(format!("{} {}", mtime, meta.len()));
+    }
+    let stamp = stamp.join(" ");
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    let entry = dir.join(format!("{:016x}", hasher.finish()));
+
+    if let Some(output) = load_cached(&entry, &key, &stamp) {
+        if args.opts.verbose >= 1 {
+            eprintln!("{}: using cached {}", module.base, entry.display());
+        }
+        return Ok(output);
+    }
+    let output = generate(args, src, efi, addr_offset, module, unload)?;
+    if let Err(e) = store_cached(&entry, &key, &stamp, &output) {
+        eprintln!("Unable to cache output for {}: {}", module.base, e);
+    }
+    Ok(output)
+}
+
+/// Load the output cached in `entry`, with the warnings given when it was
+/// generated, if it was stored for `key` with files in the state described
+/// by `stamp`.
+fn load_cached(entry: &Path, key: &str, stamp: &str) -> Option<ModOutput> {
+    let text = std::fs::read_to_string(entry).ok()?;
+    let mut lines = text.lines();
+    if lines.next()? != key || lines.next()? != stamp {
+        return None;
+    }
+    let counts: Vec<usize> = lines
+        .next()?
+        .split(' ')
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    let [funcs, objs, nwarnings] = counts[..] else {
+        return None;
+    };
+    let warnings: Vec<String> =
+        lines.by_ref().take(nwarnings).map(str::to_string).collect();
+    if warnings.len() != nwarnings {
+        return None;
+    }
+    Some(ModOutput {
+        cleanup: Vec::new(),
+        warnings,
+        funcs,
+        objs,
+        lines: lines.map(str::to_string).collect(),
+    })
+}
+
+/// Cache `output` in `entry`, replacing it whole so that concurrent readers
+/// never see a partial entry.
+fn store_cached(
+    entry: &Path,
+    key: &str,
+    stamp: &str,
+    output: &ModOutput,
+) -> Result<()> {
+    let tmp = entry.with_extension(format!("tmp{}", std::process::id()));
+    let mut file = BufWriter::new(File::create(&tmp)?);
+    writeln!(
+        file,
+        "{}\n{}\n{} {} {}",
+        key,
+        stamp,
+        output.funcs,
+        output.objs,
+        output.warnings.len()
+    )?;
+    for line in output.warnings.iter().chain(output.lines.iter()) {
+        writeln!(file, "{}", line)?;
+    }
+    file.flush()?;
+    drop(file);
+    std::fs::rename(&tmp, entry)
+}
+
+/// Generate the commands for the module `module` loaded at `addr_offset`,
+/// reading its symbols from `src`, which should match the build-id of the
+/// image `efi`, if given.
+fn generate(
+    args: &Args,
+    src: &Source,
+    efi: Option<&Path>,
+    addr_offset: u64,
+    module: &Module,
+    unload: bool,
+) -> Result<ModOutput> {
+    let file_base = module.base.as_str();
+    let name = module.name();
+    let opts = &args.opts;
+    // Only checked when the output is not cached, its cache entry being
+    // invalidated by any change to the image
+    let stale = match efi {
+        Some(efi) => stale_build_id(src.path(), efi)?,
+        None => None,
+    };
+    if let (true, Some(msg)) = (opts.strict, &stale) {
+        return Err(Error::new(ErrorKind::InvalidData, msg.clone()));
+    }
+    let mut res = match src {
+        Source::Obj(obj) => process_file(file_base, obj, addr_offset, opts)?,
+        Source::Rebased(obj, efi) => {
+            let rvas = section_rvas(efi)?;
+            process_file_rebased(obj, addr_offset, opts, Some(&rvas))?
+        }
+        Source::Efi(efi) => process_efi(efi, addr_offset, opts)?,
+        Source::Pdb(pdb) => process_pdb(pdb, addr_offset, opts)?,
+    };
+    if let Some(msg) = stale {
+        res.warnings.push(format!("{}; object may be stale", msg));
+    }
+    // Judged by the object as read
+    let coverage = res.text_coverage();
+    if let (Some(min), Some(coverage)) = (args.min_coverage, coverage) {
+        if coverage * 100.0 < min {
+            res.warnings.push(format!(
+                "functions cover only {:.1}% of .text; object may be \
+                 stripped or mismatched",
+                coverage * 100.0
+            ));
+        }
+    }
+    if let Some(limit) = args.limit {
+        res.limit(limit, &args.fopts);
+    }
+    // Added once the limit is applied, so as not to compete for its places
+    let entry = module.entry.and_then(|entry| args.shift(entry));
+    if let (true, Some(entry)) = (args.emit_entry, entry) {
+        res.add_synthetic(SymRes {
+            addr: entry,
+            name: format!("{}_entry", name),
+            size: 1,
+            is_func: true,
+            aliases: Vec::new(),
+            source: None,
+        });
+    }
+    if args.emit_bounds {
+        res.add_bounds(name);
+    }
+    if let (true, Some(guid)) = (args.guid_alias, &module.guid) {
+        // Named for the GUID, at the module's entry point, or failing that
+        // at its load address
+        res.add_mark(SymRes {
+            addr: entry.unwrap_or(addr_offset),
+            name: guid.clone(),
+            size: 0,
+            is_func: false,
+            aliases: Vec::new(),
+            source: None,
+        });
+    }
+    let (funcs, objs) = res.counts();
+    let warnings = std::mem::take(&mut res.warnings);
+    if args.format == Format::Gdb {
+        let line = if unload {
+            format!("remove-symbol-file -a 0x{:x}", res.text().start)
+        } else {
+            format_gdb(&res, src.path())
+        };
+        let lines = vec![line];
+        let cleanup = Vec::new();
+        return Ok(ModOutput { lines, cleanup, warnings, funcs, objs });
+    }
+    let fopts = &args.fopts;
+    let abs = Section {
+        name: "abs".to_string(),
+        start: 0,
+        end: 0,
+        syms: Vec::new(),
+        marks: std::mem::take(&mut res.abs),
+    };
+    let mut lines = Vec::new();
+    let mut names = Vec::new();
+    for sect in res.sections.iter().chain(std::iter::once(&abs)) {
+        if args.format == Format::Json {
+            // Each object is emitted as one element of the output array
+            let objs = format_json(sect, name, fopts);
+            lines.extend(objs.iter().map(|obj| obj.to_string()));
+        } else if args.format == Format::Table {
+            lines.extend(format_table(sect, name, fopts));
+        } else if unload {
+            lines.extend(format_nmdel(sect, name, fopts));
+        } else {
+            lines.extend(format_nmadd(sect, name, fopts));
+        }
+        if args.sort == Sort::Name {
+            names.extend(fopts.output_names(sect, name));
+        }
+    }
+    if args.sort == Sort::Name {
+        // Sizes were computed in address order, before this
+        let mut sorted: Vec<(String, String)> =
+            names.into_iter().zip(lines).collect();
+        sorted.sort();
+        lines = sorted.into_iter().map(|(_, line)| line).collect();
+    } else if args.format == Format::Table {
+        // Rows lead with the zero-padded address, placing absolute symbols
+        // among the rest
+        lines.sort();
+    }
+    Ok(ModOutput { lines, cleanup: Vec::new(), warnings, funcs, objs })
+}
+
+/// Process the (address, module) pairs in `mods` across up to `args.jobs`
+/// threads, returning the results in the same order as `mods`.  With
+/// --fail-fast, no further modules are started once one fails, and the
+/// results stop short at the first of those not started.
+fn process_all(
+    args: &Args,
+    objs: &ObjDir,
+    mods: &[(u64, &Module)],
+) -> Vec<Result<ModOutput>> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results = Mutex::new((0..mods.len()).map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|s| {
+        for _ in 0..args.jobs.min(mods.len()) {
+            s.spawn(|| loop {
+                if args.fail_fast && failed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let (addr, module) = match mods.get(idx) {
+                    Some(m) => *m,
+                    None => break,
+                };
+                let res = process_module(args, objs, addr, module, args.unload);
+                if res.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                results.lock().unwrap()[idx] = Some(res);
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().map_while(|res| res).collect()
+}
+
+/// Comment lines recording how the output was generated
+fn header(args: &Args) -> Vec<String> {
+    let comment = match args.format {
+        Format::Gdb => "#",
+        _ => "//",
+    };
+    let logs: Vec<String> =
+        args.inputs().iter().map(|p| p.display().to_string()).collect();
+    let dirs: Vec<String> =
+        args.obj_paths.iter().map(|p| p.display().to_string()).collect();
+    let secs =
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    vec![
+        format!(
+            "{} generated by mdb-tianocore {}",
+            comment,
+            env!("CARGO_PKG_VERSION")
+        ),
+        format!("{} debug output: {}", comment, logs.join(", ")),
+        format!("{} obj path: {}", comment, dirs.join(", ")),
+        format!("{} at: {}", comment, utc_timestamp(secs)),
+    ]
+}
+
+/// Format `secs` since the epoch as an ISO 8601 UTC timestamp
+fn utc_timestamp(secs: u64) -> String {
+    // Convert days since the epoch to a civil date, per Howard Hinnant's
+    // `civil_from_days` algorithm
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let tod = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        tod / 3600,
+        tod / 60 % 60,
+        tod % 60
+    )
+}
+
+/// Writes the output for each module as it is processed, keeping count
+struct Emitter {
+    out: Box<dyn Write>,
+    /// Where to write the commands undoing the output, for --cleanup
+    cleanup: Option<Box<dyn Write>>,
+    /// JSON output is a single array spanning every module
+    json: bool,
+    sep: &'static str,
+    /// Comments with which to start each output file
+    header: Vec<String>,
+    /// Directory in which to write a file for each module instead
+    split_dir: Option<PathBuf>,
+    split_empty: bool,
+    ext: &'static str,
+    summary: bool,
+    /// Whether to hold back the errors for each module
+    quiet: bool,
+    /// Whether to exit at the first module which cannot be processed
+    fail_fast: bool,
+    total: usize,
+    failed: usize,
+    nfuncs: usize,
+    nobjs: usize,
+}
+
+/// Create the output file at `path`, exiting if it cannot be
+fn create_output(path: &Path) -> Box<dyn Write> {
+    let file = File::create(path).unwrap_or_else(|e| {
+        eprintln!("Unable to create {}: {}", path.display(), e);
+        std::process::exit(1);
+    });
+    Box::new(BufWriter::new(file))
+}
+
+impl Emitter {
+    fn new(args: &Args) -> Self {
+        let out: Box<dyn Write> = match &args.out_path {
+            Some(path) => create_output(path),
+            None => Box::new(std::io::stdout()),
+        };
+        if let Some(dir) = &args.split_dir {
+            std::fs::create_dir_all(dir).unwrap_or_else(|e| {
+                eprintln!("Unable to create {}: {}", dir.display(), e);
+                std::process::exit(1);
+            });
+        }
+        let cleanup = args.cleanup.as_deref().map(create_output);
+        let mut emitter = Emitter {
+            out,
+            cleanup,
+            json: args.format == Format::Json,
+            sep: "[",
+            header: Vec::new(),
+            split_dir: args.split_dir.clone(),
+            split_empty: args.split_empty,
+            ext: match args.format {
+                Format::Nmadd => "mdb",
+                Format::Gdb => "gdb",
+                Format::Json => "json",
+                Format::Table => "txt",
+            },
+            summary: args.summary,
+            quiet: args.quiet,
+            fail_fast: args.fail_fast,
+            total: 0,
+            failed: 0,
+            nfuncs: 0,
+            nobjs: 0,
+        };
+        if args.header && !args.list {
+            emitter.header = header(args);
+        }
+        if emitter.split_dir.is_none() {
+            for line in emitter.header.iter() {
+                writeln!(emitter.out, "{}", line).unwrap();
+            }
+        }
+        if let Some(cleanup) = &mut emitter.cleanup {
+            for line in emitter.header.iter() {
+                writeln!(cleanup, "{}", line).unwrap();
+            }
+        }
+        emitter
+    }
+
+    /// Write `output` for `module` to its own file in `dir`
+    fn write_split(
+        &self,
+        dir: &Path,
+        module: &Module,
+        output: &ModOutput,
+    ) -> Result<()> {
+        if output.lines.is_empty() && !self.split_empty {
+            return Ok(());
+        }
+        let path = dir.join(format!("{}.{}", module.name(), self.ext));
+        let mut file = BufWriter::new(File::create(path)?);
+        for line in self.header.iter() {
+            writeln!(file, "{}", line)?;
+        }
+        if self.json {
+            let mut sep = "[";
+            for line in output.lines.iter() {
+                write!(file, "{}\n  {}", sep, line)?;
+                sep = ",";
+            }
+            writeln!(file, "{}\n]", if sep == "[" { "[" } else { "" })?;
+        } else {
+            for line in output.lines.iter() {
+                writeln!(file, "{}", line)?;
+            }
+        }
+        file.flush()
+    }
+
+    /// Write the output `res` for `module`, loaded at `addr`.  With
+    /// --fail-fast, a failure closes the output, leaving it complete up to
+    /// that module, and false is returned for the caller to stop.
+    fn emit(
+        &mut self,
+        addr: u64,
+        module: &Module,
+        res: Result<ModOutput>,
+    ) -> bool {
+        self.total += 1;
+        let output = match res {
+            Ok(output) => output,
+            Err(e) => {
+                if !self.quiet {
+                    eprintln!("Error processing {}: {}", module.base, e);
+                }
+                self.failed += 1;
+                if self.fail_fast {
+                    self.close();
+                }
+                return !self.fail_fast;
+            }
+        };
+        if self.summary {
+            eprintln!(
+                "module {}: {} funcs, {} objs @ 0x{:x}",
+                module.base, output.funcs, output.objs, addr
+            );
+        }
+        if let Some(dir) = &self.split_dir {
+            if let Err(e) = self.write_split(dir, module, &output) {
+                if !self.quiet {
+                    eprintln!(
+                        "Error writing output for {}: {}",
+                        module.base, e
                     );
                 }
+                self.failed += 1;
+                return true;
             }
+        }
+        self.nfuncs += output.funcs;
+        self.nobjs += output.objs;
+        if let Some(cleanup) = &mut self.cleanup {
+            for line in output.cleanup.iter() {
+                writeln!(cleanup, "{}", line).unwrap();
+            }
+        }
+        if self.split_dir.is_some() {
+            return true;
+        }
+        for line in output.lines {
+            if self.json {
+                write!(self.out, "{}\n  {}", self.sep, line).unwrap();
+                self.sep = ",";
+            } else {
+                writeln!(self.out, "{}", line).unwrap();
+            }
+        }
+        true
+    }
+
+    /// End the JSON array, if one is being written, and flush the output
+    /// and the cleanup commands
+    fn close(&mut self) {
+        if self.json && self.split_dir.is_none() {
+            let open = if self.sep == "[" { "[" } else { "" };
+            writeln!(self.out, "{}\n]", open).unwrap();
+        }
+        self.out.flush().unwrap();
+        if let Some(cleanup) = &mut self.cleanup {
+            cleanup.flush().unwrap();
+        }
+    }
+
+    /// Complete the output, exiting with a failure status if modules could
+    /// not be processed
+    fn finish(mut self, strict: bool) {
+        self.close();
+        if self.summary {
+            eprintln!(
+                "total: {} funcs, {} objs in {} of {} modules",
+                self.nfuncs,
+                self.nobjs,
+                self.total - self.failed,
+                self.total
+            );
+        }
+        if self.failed > 0 {
+            eprintln!(
+                "{} of {} modules could not be processed",
+                self.failed, self.total
+            );
+        }
+        if self.failed == self.total || (strict && self.failed > 0) {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Whether `module` is selected by the --module patterns, and with
+/// --require-started, was started
+fn selected(args: &Args, module: &Module) -> bool {
+    if args.require_started && !module.started {
+        return false;
+    }
+    args.modules.is_empty()
+        || args.modules.iter().any(|pat| pat.matches(&module.base))
+}
+
+/// Why no module was selected, naming --require-started if it applied
+fn none_selected(args: &Args) -> &'static str {
+    match (args.require_started, args.modules.is_empty()) {
+        (true, true) => "No loaded modules were started (--require-started)",
+        (true, false) => "No started modules match the --module patterns",
+        (false, _) => "No loaded modules match the --module patterns",
+    }
+}
+
+/// The selected modules of `map`, in the order of --sort, other than those
+/// loaded below the --relative-to module, which have no address relative to
+/// it
+fn to_process<'a>(
+    args: &Args,
+    map: &'a debuglog::ModMap,
+) -> Vec<(u64, &'a Module)> {
+    let mut mods: Vec<(u64, &Module)> = map
+        .iter()
+        .filter(|(_, module)| selected(args, module))
+        .filter(|(addr, module)| {
+            let below = **addr < args.rebase;
+            if below && args.opts.verbose >= 1 {
+                eprintln!(
+                    "skipping {} at 0x{:x}, below the --relative-to module",
+                    module.base, addr
+                );
+            }
+            !below
+        })
+        .map(|(addr, module)| (*addr, module))
+        .collect();
+    if args.sort == Sort::Name {
+        mods.sort_by_key(|(addr, module)| (module.name(), *addr));
+    }
+    mods
+}
+
+/// Prepare to search for the object files
+fn open_objs(args: &Args) -> ObjDir {
+    let mut objs = if args.recursive {
+        ObjDir::recursive(&args.obj_paths, &args.obj_ext).unwrap_or_else(|e| {
+            eprintln!("Error searching the obj path: {}", e);
+            std::process::exit(1);
+        })
+    } else {
+        ObjDir::new(&args.obj_paths, &args.obj_ext)
+    };
+    for dup in objs.duplicates() {
+        eprintln!("{}", dup);
+    }
+    if let Some(path) = &args.map_path {
+        objs.load_map(path).unwrap_or_else(|e| {
+            eprintln!("Error reading map {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+    }
+    objs
+}
+
+/// Name each module loaded at several addresses in `map` after its address,
+/// so that the symbols of its images are told apart, returning a warning
+/// for each such module, for the caller to report.
+fn disambiguate(map: &mut debuglog::ModMap) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut addrs: HashMap<String, Vec<u64>> = HashMap::new();
+    for (addr, module) in map.iter() {
+        addrs.entry(module.base.clone()).or_default().push(*addr);
+    }
+    for (addr, module) in map.iter_mut() {
+        let others = &addrs[&module.base];
+        if others.len() < 2 {
             continue;
         }
-        // Handle cases where an image load/start fails:
-        // "Error: Image at <addr> start failed: ..."
-        if line.starts_with("Error: Image at ") && fields.get(3).is_some() {
-            if let Ok(addr_parsed) =
-                u64::from_str_radix(fields.get(3).unwrap(), 16)
-            {
-                map.remove(&addr_parsed);
+        if others[0] == *addr {
+            let list: Vec<String> =
+                others.iter().map(|a| format!("0x{:x}", a)).collect();
+            warnings.push(format!(
+                "Module {} loaded at multiple addresses: {}",
+                module.base,
+                list.join(", ")
+            ));
+        }
+        module.name = Some(format!("{}_{:x}", module.base, addr));
+    }
+    warnings
+}
+
+/// Describe `map` in the format `fmt`, for --dump-map.  Unlike --list, this
+/// includes every module, whether or not selected.
+fn map_text(map: &debuglog::ModMap, fmt: MapFormat) -> String {
+    let hex = |addr: Option<u64>| addr.map(|addr| format!("0x{:x}", addr));
+    match fmt {
+        MapFormat::Json => {
+            let mods: Vec<serde_json::Value> = map
+                .iter()
+                .map(|(addr, module)| {
+                    serde_json::json!({
+                        "addr": hex(Some(*addr)),
+                        "module": module.base,
+                        "name": module.name(),
+                        "entry": hex(module.entry),
+                        "started": module.started,
+                        "guid": module.guid,
+                    })
+                })
+                .collect();
+            serde_json::to_string_pretty(&mods).unwrap() + "\n"
+        }
+        MapFormat::Tsv => {
+            let mut text =
+                String::from("addr\tmodule\tname\tentry\tstarted\tguid\n");
+            for (addr, module) in map.iter() {
+                text.push_str(&format!(
+                    "0x{:x}\t{}\t{}\t{}\t{}\t{}\n",
+                    addr,
+                    module.base,
+                    module.name(),
+                    hex(module.entry).unwrap_or_default(),
+                    module.started,
+                    module.guid.as_deref().unwrap_or("")
+                ));
             }
+            text
+        }
+    }
+}
+
+/// Write out `map` in the format `fmt`, for --dump-map
+fn dump_map(args: &Args, map: &debuglog::ModMap, fmt: MapFormat) {
+    let mut emitter = Emitter::new(args);
+    write!(emitter.out, "{}", map_text(map, fmt)).unwrap();
+    emitter.out.flush().unwrap();
+}
+
+/// Read the --addrmap at `path`, exiting with an error if it is unreadable
+fn read_addrmap(path: &Path) -> debuglog::ModMap {
+    let map = File::open(path).and_then(|file| {
+        debuglog::parse_addrmap(std::io::BufReader::new(file))
+    });
+    map.unwrap_or_else(|e| {
+        eprintln!("Error reading address map {}: {}", path.display(), e);
+        std::process::exit(1);
+    })
+}
+
+/// Exit with an error for the debug output at `path`, which could not be read
+fn log_error(path: &Path, e: Error) -> ! {
+    eprintln!("Error reading debug output {}: {}", path.display(), e);
+    std::process::exit(1);
+}
+
+fn no_loads(args: &Args) -> ! {
+    let names: Vec<String> =
+        args.inputs().iter().map(|p| p.display().to_string()).collect();
+    eprintln!("No module loads found in {}", names.join(", "));
+    std::process::exit(2);
+}
+
+/// Read the next line of `reader` into `line`, returning false at the end of
+/// the log.  When following, the end is instead awaited indefinitely, as the
+/// log grows.  As with [`debuglog::lines`], bytes which are not UTF-8 are
+/// replaced.
+fn next_line(
+    args: &Args,
+    reader: &mut dyn BufRead,
+    line: &mut String,
+) -> Result<bool> {
+    let mut bytes = Vec::new();
+    loop {
+        let read = reader.read_until(b'\n', &mut bytes)?;
+        if (read == 0 || !bytes.ends_with(b"\n")) && args.follow {
+            // Await the remainder of the line
+            std::thread::sleep(FOLLOW_INTERVAL);
             continue;
         }
+        *line = String::from_utf8_lossy(&bytes).into_owned();
+        return Ok(!bytes.is_empty());
+    }
+}
+
+/// Process each module as soon as its load is read from the logs, rather
+/// than once they have been read in full.  When following, unloads are
+/// tracked too, emitting commands to remove the symbols with --unload.
+fn stream(args: &Args) {
+    let started = Instant::now();
+    let objs = open_objs(args);
+    let mut emitter = Emitter::new(args);
+    let mut loaded = debuglog::ModMap::new();
+    // Where each module was processed
+    let mut processed: HashMap<String, u64> = HashMap::new();
+    let mut loads = 0;
+    let mut line = String::new();
+    for dbg in args.dbg_outputs.iter() {
+        let mut reader =
+            debuglog::open(dbg).unwrap_or_else(|e| log_error(dbg, e));
+        while next_line(args, &mut reader, &mut line)
+            .unwrap_or_else(|e| log_error(dbg, e))
+        {
+            let (addr, module, unload) =
+                match debuglog::parse_event(&line, args.addr_radix) {
+                    Some(Event::Load(addr, module)) => {
+                        loads += 1;
+                        if args.opts.verbose >= 1 {
+                            eprintln!("loaded {} at 0x{:x}", module.base, addr);
+                        }
+                        match processed.get(&module.base) {
+                            // A repeat of the same load
+                            Some(prev) if *prev == addr => continue,
+                            Some(prev) => {
+                                eprintln!(
+                                    "Module {} loaded at multiple addresses: \
+                                 using 0x{:x}, ignoring 0x{:x}",
+                                    module.base, prev, addr
+                                );
+                                continue;
+                            }
+                            None => {}
+                        }
+                        processed.insert(module.base.clone(), addr);
+                        loaded.insert(addr, module.clone());
+                        (addr, module, args.unload && !args.follow)
+                    }
+                    Some(Event::Unload(addr)) => {
+                        let module = match loaded.remove(&addr) {
+                            Some(module) => module,
+                            None => continue,
+                        };
+                        processed.remove(&module.base);
+                        if !(args.follow && args.unload) {
+                            continue;
+                        }
+                        (addr, module, true)
+                    }
+                    Some(Event::Start(_)) | None => continue,
+                };
+            if selected(args, &module) {
+                let res = process_module(args, &objs, addr, &module, unload);
+                if !emitter.emit(addr, &module, res) {
+                    std::process::exit(1);
+                }
+                emitter.out.flush().unwrap();
+                if let Some(cleanup) = &mut emitter.cleanup {
+                    cleanup.flush().unwrap();
+                }
+            }
+        }
+    }
+    if loads == 0 {
+        no_loads(args);
+    }
+    if emitter.total == 0 {
+        eprintln!("{}", none_selected(args));
+        std::process::exit(2);
+    }
+    if args.timing {
+        eprintln!(
+            "timing: read logs and processed {} modules: {:.2?}",
+            emitter.total,
+            started.elapsed()
+        );
+    }
+    emitter.finish(args.opts.strict);
+}
+
+fn main() {
+    let argv = std::env::args_os().skip(1).collect();
+    let mut args = parse_args(argv).unwrap_or_else(|| usage());
+    if !args.list {
+        args.obj_paths =
+            args.obj_paths.iter().map(|p| check_obj_path(p)).collect();
+    }
+    // The cache only saves time, so its absence is no reason to stop
+    if let Some(dir) = &args.cache_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!(
+                "Warning: unable to create cache directory {}: {}; \
+                 continuing without it",
+                dir.display(),
+                e
+            );
+            args.cache_dir = None;
+        }
+    }
+    if args.stream && !args.list {
+        stream(&args);
+        return;
+    }
+
+    // Logs from successive boots are merged, with later loads at the same
+    // address, or of the same module, taking precedence.
+    let started = Instant::now();
+    let mut map = match (&args.single, &args.addrmap) {
+        (Some((_, addr, base)), _) => {
+            let module = Module {
+                base: base.clone(),
+                entry: None,
+                name: None,
+                started: true,
+                guid: None,
+            };
+            debuglog::ModMap::from([(*addr, module)])
+        }
+        (None, Some(path)) => read_addrmap(path),
+        (None, None) => debuglog::ModMap::new(),
+    };
+    for dbg in args.dbg_outputs.iter() {
+        let bufr = debuglog::open(dbg).unwrap_or_else(|e| log_error(dbg, e));
+        let moved = debuglog::parse_log(
+            &mut map,
+            bufr,
+            args.addr_radix,
+            args.disambiguate,
+        )
+        .unwrap_or_else(|e| log_error(dbg, e));
+        for Moved { base, from, to } in moved {
+            eprintln!(
+                "Module {} loaded at multiple addresses: using 0x{:x}, \
+                 ignoring 0x{:x}",
+                base, to, from
+            );
+        }
+    }
+    if args.disambiguate {
+        for warning in disambiguate(&mut map) {
+            eprintln!("{}", warning);
+        }
+    }
+    if args.timing {
+        eprintln!("timing: read logs: {:.2?}", started.elapsed());
+    }
+    if map.is_empty() {
+        no_loads(&args);
+    }
+    if let Some(base) = &args.relative_to {
+        match map.iter().find(|(_, module)| module.base == *base) {
+            Some((addr, _)) => args.rebase = *addr,
+            None => {
+                eprintln!(
+                    "Module {} given to --relative-to is not loaded",
+                    base
+                );
+                std::process::exit(1);
+            }
+        }
     }
-    for (addr_offset, file_base) in map.iter() {
-        let obj = obj_dir.join(format!("{}.debug", file_base));
-        if let Err(e) = process_file(file_base, &obj, *addr_offset) {
-            eprintln!("Error processing {}: {:?}", file_base, e);
+    if args.opts.verbose >= 1 {
+        for (addr, module) in map.iter() {
+            eprintln!("loaded {} at 0x{:x}", module.base, addr);
         }
     }
+    if let Some(fmt) = args.dump_map {
+        dump_map(&args, &map, fmt);
+        return;
+    }
+    let mods = to_process(&args, &map);
+    if mods.is_empty() {
+        eprintln!("{}", none_selected(&args));
+        std::process::exit(2);
+    }
+    let mut emitter = Emitter::new(&args);
+    if args.list {
+        for (addr, module) in mods.iter() {
+            match module.entry {
+                Some(entry) => writeln!(
+                    emitter.out,
+                    "0x{:x} {} EntryPoint=0x{:x}",
+                    addr,
+                    module.name(),
+                    entry
+                ),
+                None => writeln!(emitter.out, "0x{:x} {}", addr, module.name()),
+            }
+            .unwrap();
+        }
+        emitter.out.flush().unwrap();
+        return;
+    }
+    let objs = open_objs(&args);
+    let started = Instant::now();
+    let results = process_all(&args, &objs, &mods);
+    if args.timing {
+        eprintln!(
+            "timing: processed {} modules: {:.2?}",
+            mods.len(),
+            started.elapsed()
+        );
+    }
+    for ((addr, module), res) in mods.iter().zip(results) {
+        if !emitter.emit(*addr, module, res) {
+            std::process::exit(1);
+        }
+    }
+    emitter.finish(args.opts.strict);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join(name)
+    }
+
+    /// A scratch directory for a test, removed once done with
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(test: &str) -> Self {
+            let name = format!("mdb-edk2-main-{}-{}", std::process::id(), test);
+            let path = std::env::temp_dir().join(name);
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn join(&self, name: &str) -> PathBuf {
+            self.0.join(name)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Parse the command line `line`, which must be valid, splitting the
+    /// arguments at whitespace
+    fn args(line: &str) -> Args {
+        parse_args(line.split_whitespace().map(OsString::from).collect())
+            .unwrap()
+    }
+
+    fn module(base: &str) -> Module {
+        Module {
+            base: base.to_string(),
+            entry: None,
+            name: None,
+            started: true,
+            guid: None,
+        }
+    }
+
+    fn output(lines: &[&str]) -> ModOutput {
+        ModOutput {
+            lines: lines.iter().map(|line| line.to_string()).collect(),
+            cleanup: Vec::new(),
+            warnings: Vec::new(),
+            funcs: lines.len(),
+            objs: 0,
+        }
+    }
+
+    fn failure() -> Result<ModOutput> {
+        Err(Error::new(ErrorKind::NotFound, "file Bar.debug not found"))
+    }
+
+    #[test]
+    fn fail_fast_closes_json() {
+        let dir = TempDir::new("fail_fast_closes_json");
+        let out = dir.join("out.json");
+        let args = args(&format!(
+            "-d objs --format json --fail-fast -o {} log",
+            out.display()
+        ));
+        let mut emitter = Emitter::new(&args);
+        let json = r#"{"addr":"0x1000","name":"foo"}"#;
+        assert!(emitter.emit(0x1000, &module("Foo"), Ok(output(&[json]))));
+        assert!(!emitter.emit(0x2000, &module("Bar"), failure()));
+        let text = std::fs::read_to_string(&out).unwrap();
+        let val: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(val, serde_json::json!([{"addr": "0x1000", "name": "foo"}]));
+    }
+
+    #[test]
+    fn fail_fast_flushes_cleanup() {
+        let dir = TempDir::new("fail_fast_flushes_cleanup");
+        let (out, clean) = (dir.join("add.mdb"), dir.join("clean.mdb"));
+        let args = args(&format!(
+            "-d objs --fail-fast --cleanup {} -o {} log",
+            clean.display(),
+            out.display()
+        ));
+        let mut emitter = Emitter::new(&args);
+        let mut foo = output(&["1000::nmadd -f -s 10 \"Foo.foo\""]);
+        foo.cleanup = vec!["::nmdel \"Foo.foo\"".to_string()];
+        assert!(emitter.emit(0x1000, &module("Foo"), Ok(foo)));
+        assert!(!emitter.emit(0x2000, &module("Bar"), failure()));
+        let out = std::fs::read_to_string(&out).unwrap();
+        assert_eq!(
+            out.lines().last(),
+            Some("1000::nmadd -f -s 10 \"Foo.foo\"")
+        );
+        let clean = std::fs::read_to_string(&clean).unwrap();
+        assert_eq!(clean.lines().last(), Some("::nmdel \"Foo.foo\""));
+    }
+
+    #[test]
+    fn require_started() {
+        let mut idle = module("Bar");
+        idle.started = false;
+        let opts = args("-d objs --require-started log");
+        assert!(selected(&opts, &module("Foo")));
+        assert!(!selected(&opts, &idle));
+        assert_eq!(
+            none_selected(&opts),
+            "No loaded modules were started (--require-started)"
+        );
+        let opts = args("-d objs --require-started --module Bar log");
+        assert!(!selected(&opts, &module("Foo")));
+        assert!(!selected(&opts, &idle));
+        assert_eq!(
+            none_selected(&opts),
+            "No started modules match the --module patterns"
+        );
+        let opts = args("-d objs --module Bar log");
+        assert!(selected(&opts, &idle));
+    }
+
+    #[test]
+    fn sorted_by_addr_or_name() {
+        let src = Source::Obj(testdata("dwarf/Line4.debug"));
+        let lines = |line: &str| {
+            let opts = args(line);
+            let out = generate(&opts, &src, None, 0, &module("Foo"), false);
+            out.unwrap().lines
+        };
+        let foo = "210::nmadd -f -s 4 \"Foo.foo\"";
+        let bar = "214::nmadd -f -s 10 \"Foo.bar\"";
+        assert_eq!(lines("-d objs log"), [foo, bar]);
+        assert_eq!(lines("-d objs --sort addr log"), [foo, bar]);
+        // With the same lines, only reordered
+        assert_eq!(lines("-d objs --sort name log"), [bar, foo]);
+    }
+
+    #[test]
+    fn guid_alias_at_entry() {
+        let src = Source::Obj(testdata("dwarf/Line4.debug"));
+        let opts = args("-d objs --guid-alias log");
+        let mut foo = module("Foo");
+        foo.entry = Some(0x210);
+        let lines = |module: &Module| {
+            let out = generate(&opts, &src, None, 0, module, false);
+            out.unwrap().lines
+        };
+        let plain = lines(&foo);
+        assert_eq!(plain.len(), 2);
+        // Nothing more without a GUID from the log
+        let unaliased = args("-d objs log");
+        let out = generate(&unaliased, &src, None, 0, &foo, false);
+        assert_eq!(out.unwrap().lines, plain);
+
+        foo.guid = Some("1A1E4886-9517-440E-9FDE-3BE44CEE2136".to_string());
+        let guid = lines(&foo);
+        assert_eq!(guid[..2], plain);
+        assert_eq!(
+            guid[2..],
+            ["210::nmadd -o -s 0 \"Foo.1A1E4886-9517-440E-9FDE-3BE44CEE2136\""]
+        );
+    }
+
+    #[test]
+    fn relative_to_reference() {
+        let mut map = debuglog::ModMap::new();
+        map.insert(0x7e00_0000, module("Line5"));
+        map.insert(0x7f00_0000, module("Line4"));
+        let mut opts = args("-d objs --relative-to Line4 log");
+        // As found by main
+        opts.rebase = 0x7f00_0000;
+        let mods = to_process(&opts, &map);
+        let bases: Vec<(u64, &str)> =
+            mods.iter().map(|(addr, m)| (*addr, m.base.as_str())).collect();
+        assert_eq!(bases, [(0x7f00_0000, "Line4")]);
+
+        let objs = ObjDir::new(&[testdata("dwarf")], "debug");
+        let (addr, line4) = mods[0];
+        let out = process_module(&opts, &objs, addr, line4, false).unwrap();
+        assert_eq!(
+            out.lines,
+            [
+                "210::nmadd -f -s 4 \"Line4.foo\"",
+                "214::nmadd -f -s 10 \"Line4.bar\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn map_dumped() {
+        let mut foo = module("Foo");
+        foo.entry = Some(0x7f00_0240);
+        foo.guid = Some("1A1E4886-9517-440E-9FDE-3BE44CEE2136".to_string());
+        let mut bar = module("Bar");
+        bar.started = false;
+        let map =
+            debuglog::ModMap::from([(0x7f00_0000, foo), (0x7e00_0000, bar)]);
+        let json = map_text(&map, MapFormat::Json);
+        let val: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            val,
+            serde_json::json!([
+                {
+                    "addr": "0x7e000000",
+                    "module": "Bar",
+                    "name": "Bar",
+                    "entry": null,
+                    "started": false,
+                    "guid": null,
+                },
+                {
+                    "addr": "0x7f000000",
+                    "module": "Foo",
+                    "name": "Foo",
+                    "entry": "0x7f000240",
+                    "started": true,
+                    "guid": "1A1E4886-9517-440E-9FDE-3BE44CEE2136",
+                },
+            ])
+        );
+        assert_eq!(
+            map_text(&map, MapFormat::Tsv),
+            concat!(
+                "addr\tmodule\tname\tentry\tstarted\tguid\n",
+                "0x7e000000\tBar\tBar\t\tfalse\t\n",
+                "0x7f000000\tFoo\tFoo\t0x7f000240\ttrue\t",
+                "1A1E4886-9517-440E-9FDE-3BE44CEE2136\n",
+            )
+        );
+    }
+
+    #[test]
+    fn limit_leaves_entry_out() {
+        let src = Source::Obj(testdata("dwarf/Line4.debug"));
+        let mut foo = module("Foo");
+        foo.entry = Some(0x210);
+        let opts = args("-d objs --limit 1 --emit-entry log");
+        let out = generate(&opts, &src, None, 0, &foo, false).unwrap();
+        // foo is cut, leaving its address to the entry symbol
+        assert_eq!(
+            out.lines,
+            [
+                "210::nmadd -f -s 1 \"Foo.Foo_entry\"",
+                "214::nmadd -f -s 10 \"Foo.bar\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn config_options_listed_in_usage() {
+        for (_, names, valued) in CONFIG_OPTIONS {
+            let head = match valued {
+                true => format!("{} <", names.last().unwrap()),
+                false => format!("{} ", names.last().unwrap()),
+            };
+            assert!(USAGE.contains(&format!(" {}", head)), "{}", head);
+        }
+    }
+
+    #[test]
+    fn config_overridden_by_command_line() {
+        let dir = TempDir::new("config_overridden_by_command_line");
+        let config = dir.join("config.toml");
+        std::fs::write(
+            &config,
+            concat!(
+                "obj-path = [\"a\", \"b\"]\n",
+                "format = \"gdb\"\n",
+                "data = true\n",
+                "locals = false\n",
+                "verbose = 2\n",
+                "quiet = true\n",
+            ),
+        )
+        .unwrap();
+        let line =
+            format!("--config {} --format json -q log", config.display());
+        let mut argv: Vec<OsString> =
+            line.split_whitespace().map(OsString::from).collect();
+        let defaults = config_args(&mut argv).unwrap();
+        assert_eq!(argv, ["--format", "json", "-q", "log"]);
+        assert_eq!(defaults, ["-d", "a", "-d", "b", "--data", "-v", "-v"]);
+
+        let opts = args(&line);
+        assert!(opts.format == Format::Json && opts.quiet && opts.opts.data);
+        assert_eq!(opts.obj_paths, [PathBuf::from("a"), PathBuf::from("b")]);
+        assert_eq!(opts.opts.verbose, 2);
+        // Given on the command line, -d replaces the configured obj path
+        let opts = args(&format!("--config={} -d c log", config.display()));
+        assert_eq!(opts.obj_paths, [PathBuf::from("c")]);
+
+        for (text, err) in [
+            ("help = true", "help: unknown option"),
+            ("data = \"yes\"", "data: expected true or false"),
+            ("format = true", "format: expected a value, not a boolean"),
+        ] {
+            std::fs::write(&config, text).unwrap();
+            let mut argv =
+                vec![OsString::from("--config"), config.clone().into()];
+            let msg = config_args(&mut argv).err().unwrap().to_string();
+            assert_eq!(msg, format!("{}: {}", config.display(), err));
+        }
+    }
+
+    #[test]
+    fn cached_output_keeps_warnings() {
+        let dir = TempDir::new("cached_output_keeps_warnings");
+        let (objs, cache) = (dir.join("objs"), dir.join("cache"));
+        std::fs::create_dir(&objs).unwrap();
+        std::fs::create_dir(&cache).unwrap();
+        std::fs::copy(testdata("bid/Stale.debug"), objs.join("B.debug"))
+            .unwrap();
+        std::fs::copy(testdata("bid/B.efi"), objs.join("B.efi")).unwrap();
+        let opts = args(&format!(
+            "-d {} --cache-dir {} log",
+            objs.display(),
+            cache.display()
+        ));
+        let dirs = ObjDir::new(std::slice::from_ref(&objs), "debug");
+        let run = || module_output(&opts, &dirs, 0, &module("B"), false, true);
+        let first = run().unwrap();
+        let entries: Vec<_> = std::fs::read_dir(&cache).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        let entry = entries[0].as_ref().unwrap().path();
+        assert!(std::fs::read_to_string(entry).unwrap().contains("stale"));
+        let second = run().unwrap();
+        assert_eq!(second.lines, first.lines);
+        assert_eq!(
+            second.warnings,
+            [format!(
+                "build-id of {} does not match {}; object may be stale",
+                objs.join("B.debug").display(),
+                objs.join("B.efi").display()
+            )]
+        );
+    }
+
+    #[test]
+    fn disambiguated_by_address() {
+        let text = concat!(
+            "Loading driver at 0x7F000000 Foo.efi\n",
+            "Loading driver at 0x7E000000 Bar.efi\n",
+            "Loading driver at 0x7D000000 Foo.efi\n",
+        );
+        let mut map = debuglog::ModMap::new();
+        debuglog::parse_log(&mut map, text.as_bytes(), Radix::Hex, true)
+            .unwrap();
+        let warnings = disambiguate(&mut map);
+        assert_eq!(
+            warnings,
+            ["Module Foo loaded at multiple addresses: 0x7d000000, 0x7f000000"]
+        );
+        let names: Vec<(u64, &str)> =
+            map.iter().map(|(addr, m)| (*addr, m.name())).collect();
+        assert_eq!(
+            names,
+            [
+                (0x7d00_0000, "Foo_7d000000"),
+                (0x7e00_0000, "Bar"),
+                (0x7f00_0000, "Foo_7f000000"),
+            ]
+        );
+    }
+
+    #[test]
+    fn stale_build_id_detected() {
+        let efi = testdata("bid/B.efi");
+        let obj = testdata("bid/B.debug");
+        assert_eq!(stale_build_id(&obj, &efi).unwrap(), None);
+        let stale = testdata("bid/Stale.debug");
+        assert_eq!(
+            stale_build_id(&stale, &efi).unwrap().unwrap(),
+            format!(
+                "build-id of {} does not match {}",
+                stale.display(),
+                efi.display()
+            )
+        );
+        // Nothing to go on without a build-id in the image
+        let efi = testdata("pe/Foo.efi");
+        assert_eq!(stale_build_id(&stale, &efi).unwrap(), None);
+    }
 }
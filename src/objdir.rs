@@ -0,0 +1,286 @@
+//! Locating the object file for each module.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// A search path of directories holding `<module>.<ext>` object files, in
+/// which the first directory holding a module's file takes precedence
+pub struct ObjDir {
+    roots: Vec<PathBuf>,
+    ext: String,
+    /// When searching recursively, the candidate paths for each file name,
+    /// in the order of their roots, then sorted so that the choice among
+    /// duplicates is deterministic
+    index: Option<HashMap<String, Vec<PathBuf>>>,
+    /// Explicit object paths for modules, taking precedence over the search
+    overrides: HashMap<String, PathBuf>,
+    /// The object files found more than once in the index, with the choice
+    /// made among them, for the caller to report
+    duplicates: Vec<String>,
+}
+
+impl ObjDir {
+    /// Objects located directly within `roots`
+    pub fn new(roots: &[PathBuf], ext: &str) -> Self {
+        ObjDir {
+            roots: roots.to_vec(),
+            ext: ext.to_string(),
+            index: None,
+            overrides: HashMap::new(),
+            duplicates: Vec::new(),
+        }
+    }
+
+    /// Objects located anywhere beneath `roots`
+    pub fn recursive(roots: &[PathBuf], ext: &str) -> Result<Self> {
+        let mut index: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for root in roots {
+            let mut found = Vec::new();
+            walk(root, &mut found)?;
+            found.sort();
+            for path in found {
+                if let Some(name) = path.file_name().and_then(OsStr::to_str) {
+                    index.entry(name.to_string()).or_default().push(path);
+                }
+            }
+        }
+        // Other files, such as the makefiles of each module, are often
+        // repeated, but only the objects matter
+        let suffix = format!(".{}", ext);
+        let mut duplicates = Vec::new();
+        for (name, paths) in index.iter() {
+            if let (true, [first, rest @ ..]) =
+                (name.ends_with(&suffix), paths.as_slice())
+            {
+                for other in rest {
+                    duplicates.push(format!(
+                        "Multiple files for {}: using {}, ignoring {}",
+                        name,
+                        first.display(),
+                        other.display()
+                    ));
+                }
+            }
+        }
+        duplicates.sort();
+        Ok(ObjDir {
+            roots: roots.to_vec(),
+            ext: ext.to_string(),
+            index: Some(index),
+            overrides: HashMap::new(),
+            duplicates,
+        })
+    }
+
+    /// Describe the object files found more than once by [`Self::recursive`],
+    /// and which of each is used
+    pub fn duplicates(&self) -> &[String] {
+        &self.duplicates
+    }
+
+    /// Load explicit object paths from the map file at `path`, in which each
+    /// line holds a module name and the path of its object, separated by
+    /// whitespace.  Relative object paths are taken from the first root
+    /// directory, or without one, from the working directory.
+    /// Blank lines and those starting with `#` are ignored.
+    pub fn load_map(&mut self, path: &Path) -> Result<()> {
+        let text = fs::read_to_string(path)?;
+        for (num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [base, obj] => {
+                    let obj = match self.roots.first() {
+                        Some(root) => root.join(obj),
+                        None => PathBuf::from(obj),
+                    };
+                    self.overrides.insert(base.to_string(), obj);
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "line {}: expected <module> <object path>",
+                            num + 1
+                        ),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the object file for the module named `base`
+    pub fn find(&self, base: &str) -> Result<PathBuf> {
+        match self.overrides.get(base) {
+            Some(path) if !path.exists() => {
+                Err(Error::new(ErrorKind::NotFound, not_found(path)))
+            }
+            Some(path) => Ok(path.clone()),
+            None => self.find_ext(base, &self.ext),
+        }
+    }
+
+    /// Find the file for the module named `base` with extension `ext`
+    pub fn find_ext(&self, base: &str, ext: &str) -> Result<PathBuf> {
+        let name = format!("{}.{}", base, ext);
+        let searched = || {
+            let roots: Vec<String> =
+                self.roots.iter().map(|r| r.display().to_string()).collect();
+            roots.join(", ")
+        };
+        match &self.index {
+            None => {
+                let paths: Vec<PathBuf> =
+                    self.roots.iter().map(|r| r.join(&name)).collect();
+                if let Some(path) = paths.iter().find(|p| p.exists()) {
+                    return Ok(path.clone());
+                }
+                // A dangling symlink is worth pointing out, wherever it is
+                let dangling = paths.iter().find(|p| dangling(p).is_some());
+                let msg = match (paths.as_slice(), dangling) {
+                    (_, Some(path)) | ([path], None) => not_found(path),
+                    _ => format!("file {} not found in {}", name, searched()),
+                };
+                Err(Error::new(ErrorKind::NotFound, msg))
+            }
+            Some(index) => match index.get(&name).map(Vec::as_slice) {
+                Some([first, ..]) => Ok(first.clone()),
+                _ => Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("file {} not found under {}", name, searched()),
+                )),
+            },
+        }
+    }
+}
+
+/// The target of the symlink at `path`, if it is one to a missing file.  A
+/// relative target is resolved against the directory holding the link.
+pub(crate) fn dangling(path: &Path) -> Option<PathBuf> {
+    let target = fs::read_link(path).ok()?;
+    let target = path.parent().unwrap_or(Path::new("")).join(target);
+    match target.exists() {
+        true => None,
+        false => Some(target),
+    }
+}
+
+/// Describe the absence of the file at `path`
+fn not_found(path: &Path) -> String {
+    match dangling(path) {
+        Some(target) => format!(
+            "file {} is a symlink to {}, which does not exist",
+            path.display(),
+            target.display()
+        ),
+        None => format!("file {} not found", path.display()),
+    }
+}
+
+/// Collect the files beneath `dir`.  Symlinked directories are not followed,
+/// to avoid cycles.
+fn walk(dir: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+    let failed =
+        |e: Error| Error::new(e.kind(), format!("{}: {}", dir.display(), e));
+    for ent in fs::read_dir(dir).map_err(failed)? {
+        let ent = ent.map_err(failed)?;
+        let path = ent.path();
+        if ent.file_type().map_err(failed)?.is_dir() {
+            walk(&path, found)?;
+        } else {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testelf::TempDir;
+
+    #[test]
+    fn duplicates_reported_once() {
+        let dir = TempDir::new();
+        for sub in ["b", "a", "a/c"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+            fs::write(dir.join(sub).join("Foo.debug"), "").unwrap();
+            fs::write(dir.join(sub).join("Makefile"), "").unwrap();
+        }
+        let roots = [dir.path().to_path_buf()];
+        let objs = ObjDir::recursive(&roots, "debug").unwrap();
+        let first = dir.join("a/Foo.debug");
+        assert_eq!(objs.find("Foo").unwrap(), first);
+        let ignored = ["a/c/Foo.debug", "b/Foo.debug"].map(|p| {
+            format!(
+                "Multiple files for Foo.debug: using {}, ignoring {}",
+                first.display(),
+                dir.join(p).display()
+            )
+        });
+        assert_eq!(objs.duplicates(), ignored);
+
+        let missing = [dir.join("missing")];
+        let err = ObjDir::recursive(&missing, "debug").err().unwrap();
+        assert!(err.to_string().starts_with(&missing[0].display().to_string()));
+    }
+
+    #[test]
+    fn map_relative_to_first_root() {
+        let dir = TempDir::new();
+        let map = dir.join("map");
+        fs::write(&map, "# renamed\nFoo Bar.debug\n").unwrap();
+        fs::write(dir.join("Bar.debug"), "").unwrap();
+        let mut objs = ObjDir::new(&[dir.path().to_path_buf()], "debug");
+        objs.load_map(&map).unwrap();
+        assert_eq!(objs.find("Foo").unwrap(), dir.join("Bar.debug"));
+
+        // Without a root, the working directory is used
+        let mut objs = ObjDir::new(&[], "debug");
+        objs.load_map(&map).unwrap();
+        let err = objs.find("Foo").err().unwrap();
+        assert_eq!(err.to_string(), "file Bar.debug not found");
+
+        fs::write(&map, "Foo\n").unwrap();
+        let err = objs.load_map(&map).err().unwrap();
+        assert_eq!(err.to_string(), "line 1: expected <module> <object path>");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_symlink_named() {
+        use std::os::unix::fs::symlink;
+        let dir = TempDir::new();
+        fs::create_dir(dir.join("out")).unwrap();
+        fs::write(dir.join("out/Bar.debug"), "").unwrap();
+        // Relative targets are resolved against the link's directory
+        symlink("out/Bar.debug", dir.join("Bar.debug")).unwrap();
+        symlink("out/Foo.debug", dir.join("Foo.debug")).unwrap();
+        let objs = ObjDir::new(&[dir.path().to_path_buf()], "debug");
+        assert_eq!(objs.find("Bar").unwrap(), dir.join("Bar.debug"));
+        let err = objs.find("Foo").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "file {} is a symlink to {}, which does not exist",
+                dir.join("Foo.debug").display(),
+                dir.join("out/Foo.debug").display()
+            )
+        );
+
+        let err = crate::map_file(&dir.join("Foo.debug")).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains("is a symlink to"));
+        let err = crate::map_file(&dir.join("out")).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("{} is not a regular file", dir.join("out").display())
+        );
+    }
+}
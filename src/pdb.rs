@@ -0,0 +1,297 @@
+//! Symbol extraction from the `.pdb` files of builds with the Visual Studio
+//! toolchain, which produces no ELF objects.  A PDB is an MSF container of
+//! numbered streams, of which only those needed to place the public and
+//! global symbols are read: the DBI stream, locating the others, the symbol
+//! records, and the image's section headers.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::pe::ImageSyms;
+use crate::{is_noise, map_file, ObjSyms, Options};
+
+const MSF_MAGIC: &[u8] = b"Microsoft C/C++ MSF 7.00\r\n\x1aDS\0\0\0";
+
+/// The stream holding the DBI (debug information) header
+const DBI_STREAM: usize = 3;
+const DBI_HEADER_SIZE: usize = 64;
+/// Marks an absent stream, in the DBI header and optional debug header
+const NO_STREAM: u16 = 0xffff;
+/// Index of the section header stream in the optional debug header
+const DBG_SECTION_HDR: usize = 5;
+const SECTION_HDR_SIZE: usize = 40;
+const IMAGE_SCN_CNT_CODE: u32 = 0x20;
+// Machine types of 32-bit images
+const IMAGE_FILE_MACHINE_I386: u16 = 0x14c;
+const IMAGE_FILE_MACHINE_ARMNT: u16 = 0x1c4;
+
+// Symbol record kinds
+const S_LDATA32: u16 = 0x110c;
+const S_GDATA32: u16 = 0x110d;
+const S_PUB32: u16 = 0x110e;
+// Public symbol flags
+const CVPSF_CODE: u32 = 0x1;
+const CVPSF_FUNCTION: u32 = 0x2;
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad PDB: {}", msg))
+}
+
+fn u16_at(data: &[u8], off: usize) -> Option<u16> {
+    let bytes = data.get(off..off.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], off: usize) -> Option<u32> {
+    let bytes = data.get(off..off.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The streams of an MSF file, each stored as a list of blocks
+struct Msf<'a> {
+    data: &'a [u8],
+    block_size: usize,
+    /// The size and blocks of each stream
+    streams: Vec<(usize, Vec<usize>)>,
+}
+
+impl<'a> Msf<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self> {
+        if !data.starts_with(MSF_MAGIC) {
+            return Err(invalid("not an MSF 7.00 file"));
+        }
+        let field = |idx: usize| {
+            u32_at(data, MSF_MAGIC.len() + 4 * idx)
+                .map(|v| v as usize)
+                .ok_or_else(|| invalid("truncated superblock"))
+        };
+        let block_size = field(0)?;
+        let dir_size = field(3)?;
+        let block_map = field(5)?;
+        if block_size == 0 {
+            return Err(invalid("zero block size"));
+        }
+        let mut msf = Msf { data, block_size, streams: Vec::new() };
+
+        // The block map lists the blocks holding the stream directory
+        let map_start = block_map
+            .checked_mul(block_size)
+            .ok_or_else(|| invalid("bad block map"))?;
+        let dir_blocks = (0..dir_size.div_ceil(block_size))
+            .map(|i| u32_at(data, map_start.checked_add(4 * i)?))
+            .map(|b| b.map(|b| b as usize))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("truncated block map"))?;
+        let dir = msf.read(dir_size, &dir_blocks)?;
+
+        let num = u32_at(&dir, 0).ok_or_else(|| invalid("empty directory"))?;
+        let num = num as usize;
+        let mut off = num
+            .checked_mul(4)
+            .and_then(|len| len.checked_add(4))
+            .ok_or_else(|| invalid("bad directory"))?;
+        for idx in 0..num {
+            let size = match u32_at(&dir, 4 + 4 * idx) {
+                Some(u32::MAX) => 0,
+                Some(size) => size as usize,
+                None => return Err(invalid("truncated directory")),
+            };
+            let blocks = (0..size.div_ceil(block_size))
+                .map(|i| u32_at(&dir, off.checked_add(4 * i)?))
+                .map(|b| b.map(|b| b as usize))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| invalid("truncated directory"))?;
+            off += 4 * blocks.len();
+            msf.streams.push((size, blocks));
+        }
+        Ok(msf)
+    }
+
+    /// Gather the first `size` bytes stored in `blocks`
+    fn read(&self, size: usize, blocks: &[usize]) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(size);
+        for block in blocks {
+            let len = self.block_size.min(size - out.len());
+            let bytes = block
+                .checked_mul(self.block_size)
+                .and_then(|start| self.data.get(start..start.checked_add(len)?))
+                .ok_or_else(|| invalid("block beyond end of file"))?;
+            out.extend_from_slice(bytes);
+        }
+        Ok(out)
+    }
+
+    /// The contents of stream `idx`
+    fn stream(&self, idx: usize) -> Result<Vec<u8>> {
+        let (size, blocks) =
+            self.streams.get(idx).ok_or_else(|| invalid("missing stream"))?;
+        self.read(*size, blocks)
+    }
+}
+
+/// Extract the public and global symbols from the PDB at `path`, for a
+/// module loaded at `addr_start`.  Each is located by its section and
+/// offset, the section headers giving its RVA.  As with PE images, no sizes
+/// are recorded, so all are subject to stretching.
+pub fn process_pdb(
+    path: &Path,
+    addr_start: u64,
+    opts: &Options,
+) -> Result<ObjSyms> {
+    let map = map_file(path)?;
+    let msf = Msf::parse(&map)?;
+    let dbi = msf.stream(DBI_STREAM)?;
+    if dbi.len() < DBI_HEADER_SIZE {
+        return Err(invalid("truncated DBI header"));
+    }
+    let dbi_u16 = |off| u16_at(&dbi, off).unwrap();
+    let dbi_u32 = |off| u32_at(&dbi, off).unwrap() as usize;
+    let sym_stream = dbi_u16(20);
+    // The optional debug header follows the substreams of the module info,
+    // section contributions, section map, source info, type server map and
+    // EC info, in that order
+    let dbg_hdr = [24, 28, 32, 36, 40, 52]
+        .iter()
+        .try_fold(DBI_HEADER_SIZE, |off, &f| off.checked_add(dbi_u32(f)));
+    let shdr_stream = dbg_hdr
+        .filter(|_| dbi_u32(48) > 2 * DBG_SECTION_HDR)
+        .and_then(|off| u16_at(&dbi, off + 2 * DBG_SECTION_HDR))
+        .filter(|idx| *idx != NO_STREAM)
+        .ok_or_else(|| invalid("no section headers"))?;
+    let shdrs = msf.stream(usize::from(shdr_stream))?;
+
+    let addr_limit = match dbi_u16(58) {
+        IMAGE_FILE_MACHINE_I386 | IMAGE_FILE_MACHINE_ARMNT => {
+            u64::from(u32::MAX)
+        }
+        _ => u64::MAX,
+    };
+    let mut image = ImageSyms::new();
+    let mut is_code = Vec::new();
+    for hdr in shdrs.chunks_exact(SECTION_HDR_SIZE) {
+        let name = &hdr[..8];
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(8)];
+        let name = String::from_utf8_lossy(name);
+        let size = u64::from(u32_at(hdr, 8).unwrap());
+        let rva = u64::from(u32_at(hdr, 12).unwrap());
+        image.add_section(&name, addr_start, rva, size, addr_limit)?;
+        is_code.push(u32_at(hdr, 36).unwrap() & IMAGE_SCN_CNT_CODE != 0);
+    }
+    let text = image.text()?;
+    let wanted = |idx: usize| idx == text || opts.data;
+
+    let records = match sym_stream {
+        NO_STREAM => Vec::new(),
+        idx => msf.stream(usize::from(idx))?,
+    };
+    let mut read = 0;
+    let mut off = 0;
+    while let Some(len) = u16_at(&records, off) {
+        let rec = match records.get(off + 2..off + 2 + usize::from(len)) {
+            Some(rec) => rec,
+            None => break,
+        };
+        off += 2 + usize::from(len);
+        // Other kinds, such as S_UDT, may be shorter than those read here
+        if rec.len() < 12 {
+            continue;
+        }
+        // Past the kind, data and public symbols share a layout: a type
+        // index or flags, offset, section number, then name
+        let kind = u16_at(rec, 0).unwrap();
+        let is_func = match kind {
+            S_PUB32 => {
+                u32_at(rec, 2).unwrap() & (CVPSF_CODE | CVPSF_FUNCTION) != 0
+            }
+            S_GDATA32 => false,
+            S_LDATA32 if opts.locals => false,
+            _ => continue,
+        };
+        read += 1;
+        let offset = u64::from(u32_at(rec, 6).unwrap());
+        // Section numbers are 1-based
+        let idx = match usize::from(u16_at(rec, 10).unwrap()).checked_sub(1) {
+            Some(idx) if idx < is_code.len() && wanted(idx) => idx,
+            _ => continue,
+        };
+        let name = &rec[12..];
+        let name =
+            &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+        let name = String::from_utf8_lossy(name);
+        if is_noise(&name) {
+            continue;
+        }
+        let addr = match image.sections[idx].start.checked_add(offset) {
+            Some(addr) => addr,
+            None => continue,
+        };
+        image.add_sym(idx, addr, &name, is_func || is_code[idx], opts);
+    }
+    if opts.verbose >= 1 {
+        eprintln!(
+            "{}: {} public and global symbols read",
+            path.display(),
+            read
+        );
+    }
+
+    image.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testelf::{testdata, TempDir};
+    use std::fs;
+
+    fn syms(sect: &crate::Section) -> Vec<(u64, &str, bool)> {
+        let syms = sect.syms.iter();
+        syms.map(|s| (s.addr, s.name.as_str(), s.is_func)).collect()
+    }
+
+    #[test]
+    fn public_and_global_symbols() {
+        let path = testdata("pdb/Foo.pdb");
+        let opts = Options { aliases: true, ..Default::default() };
+        let obj = process_pdb(&path, 0x10000, &opts).unwrap();
+        assert_eq!((obj.text().start, obj.text().end), (0x11000, 0x11100));
+        assert_eq!(
+            syms(obj.text()),
+            [
+                (0x11010, "_ModuleEntryPoint", true),
+                (0x11040, "InternalHelper", true)
+            ]
+        );
+        assert_eq!(obj.text().syms[0].aliases, ["EfiMain"]);
+
+        let opts = Options { data: true, locals: true, ..Default::default() };
+        let obj = process_pdb(&path, 0x10000, &opts).unwrap();
+        assert_eq!(obj.sections.len(), 2);
+        assert_eq!(obj.sections[1].name, ".data");
+        assert_eq!(
+            syms(&obj.sections[1]),
+            [
+                (0x12008, "gCounter", false),
+                (0x12010, "gTable", false),
+                (0x12020, "mLocal", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_blocks_rejected() {
+        let dir = TempDir::new();
+        let mut data = fs::read(testdata("pdb/Foo.pdb")).unwrap();
+        // Point the DBI stream, past the stream count, seven sizes and the
+        // blocks of the info and type streams, far beyond the end of the file
+        let off = 512 * 4 + 40;
+        data[off..off + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let path = dir.join("Foo.pdb");
+        fs::write(&path, &data).unwrap();
+        let err = process_pdb(&path, 0, &Options::default());
+        assert_eq!(
+            err.err().unwrap().to_string(),
+            "bad PDB: block beyond end of file"
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Symbol extraction from `.efi` PE/COFF images, for modules which lack a
+//! `.debug` ELF object.  Coverage is limited to what the image retains: its
+//! COFF symbol table (if not stripped) and its export table.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use goblin::pe::section_table::{SectionTable, IMAGE_SCN_CNT_CODE};
+use goblin::pe::symbol::{
+    IMAGE_SYM_CLASS_EXTERNAL, IMAGE_SYM_CLASS_STATIC, IMAGE_SYM_DTYPE_FUNCTION,
+};
+
+use crate::{demangle, is_noise, map_file, ObjSyms, Options, Section, SymRes};
+
+/// The sections of a PE image, or of its PDB, with the symbols found in each.
+/// Neither records the sizes of symbols, so all are subject to stretching.
+pub(crate) struct ImageSyms {
+    pub(crate) sections: Vec<Section>,
+    syms: Vec<BTreeMap<u64, SymRes>>,
+}
+
+impl ImageSyms {
+    pub(crate) fn new() -> Self {
+        ImageSyms { sections: Vec::new(), syms: Vec::new() }
+    }
+
+    /// Add the section `name` of `size` bytes, at `rva` in an image loaded
+    /// at `addr_start`, which must fit below `addr_limit`
+    pub(crate) fn add_section(
+        &mut self,
+        name: &str,
+        addr_start: u64,
+        rva: u64,
+        size: u64,
+        addr_limit: u64,
+    ) -> Result<()> {
+        let start = addr_start.checked_add(rva).filter(|start| {
+            start
+                .checked_add(size.saturating_sub(1))
+                .is_some_and(|last| last <= addr_limit)
+        });
+        let (start, end) = start
+            .and_then(|start| Some((start, start.checked_add(size)?)))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "section {} at {:x} overflows the address space",
+                        name, addr_start
+                    ),
+                )
+            })?;
+        self.sections.push(Section {
+            name: name.to_string(),
+            start,
+            end,
+            syms: Vec::new(),
+            marks: Vec::new(),
+        });
+        self.syms.push(BTreeMap::new());
+        Ok(())
+    }
+
+    /// The index of the `.text` section
+    pub(crate) fn text(&self) -> Result<usize> {
+        text_index(&self.sections)
+    }
+
+    /// Add the symbol `name` at `addr` in section `idx`, demangled if asked.
+    /// The first definition at a given address wins, with any others being
+    /// its aliases.
+    pub(crate) fn add_sym(
+        &mut self,
+        idx: usize,
+        addr: u64,
+        name: &str,
+        is_func: bool,
+        opts: &Options,
+    ) {
+        let name = match opts.demangle {
+            true => demangle(name).unwrap_or_else(|| name.to_string()),
+            false => name.to_string(),
+        };
+        match self.syms[idx].get_mut(&addr) {
+            Some(res) if opts.aliases => res.add_alias(name),
+            Some(_) => {}
+            None => {
+                let res = SymRes {
+                    addr,
+                    name,
+                    size: 0,
+                    is_func,
+                    aliases: Vec::new(),
+                    source: None,
+                };
+                self.syms[idx].insert(addr, res);
+            }
+        }
+    }
+
+    /// The symbols found, with the sections ordered by address
+    pub(crate) fn finish(self) -> Result<ObjSyms> {
+        let mut sections = self.sections;
+        for (sect, syms) in sections.iter_mut().zip(self.syms) {
+            sect.syms = syms.into_values().collect();
+        }
+        sections.sort_by_key(|sect| sect.start);
+        let text = text_index(&sections)?;
+        Ok(ObjSyms { sections, text, abs: Vec::new(), warnings: Vec::new() })
+    }
+}
+
+/// The index of the `.text` section among `sections`
+fn text_index(sections: &[Section]) -> Result<usize> {
+    sections.iter().position(|s| s.name == ".text").ok_or_else(|| {
+        Error::new(ErrorKind::InvalidData, "No .text section found")
+    })
+}
+
+/// The name of `sect`, which may be stored in the string table if long
+fn sect_name(sect: &SectionTable) -> &str {
+    match &sect.real_name {
+        Some(name) => name.as_str(),
+        None => sect.name().unwrap_or(""),
+    }
+}
+
+/// Read the RVA of each section in the PE image at `path`.
+///
+/// The firmware relocates an image to its load address, so a section's
+/// runtime address is that load address plus its RVA, irrespective of the
+/// `ImageBase` the image was linked at.
+pub fn section_rvas(path: &Path) -> Result<HashMap<String, u64>> {
+    let map = map_file(path)?;
+    let pe = goblin::pe::PE::parse(&map)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    Ok(pe
+        .sections
+        .iter()
+        .map(|sect| {
+            (sect_name(sect).to_string(), u64::from(sect.virtual_address))
+        })
+        .collect())
+}
+
+/// Read the build-id recorded in the CodeView debug entry of the PE image at
+/// `path`, if it has one.  Linkers given `--build-id` store (up to the first
+/// 16 bytes of) the ID there, as the PDB signature.
+pub fn efi_build_id(path: &Path) -> Result<Option<Vec<u8>>> {
+    let map = map_file(path)?;
+    let pe = goblin::pe::PE::parse(&map)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    Ok(pe.debug_data.and_then(|data| data.guid()).map(|mut id| {
+        // The signature is a GUID, whose first three fields are stored
+        // little-endian, while the build-id is a plain byte string
+        id[0..4].reverse();
+        id[4..6].reverse();
+        id[6..8].reverse();
+        id.to_vec()
+    }))
+}
+
+/// Extract the symbols from the PE image at `path`, for a module loaded at
+/// `addr_start`.  COFF symbol values are relative to their section, whose RVA
+/// gives its offset from the load address.  Since PE symbols carry no size,
+/// all are subject to stretching.
+pub fn process_efi(
+    path: &Path,
+    addr_start: u64,
+    opts: &Options,
+) -> Result<ObjSyms> {
+    let map = map_file(path)?;
+    let pe = goblin::pe::PE::parse(&map)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+
+    let addr_limit = if pe.is_64 { u64::MAX } else { u64::from(u32::MAX) };
+    let mut image = ImageSyms::new();
+    for sect in pe.sections.iter() {
+        let size = match sect.virtual_size {
+            0 => sect.size_of_raw_data,
+            sz => sz,
+        };
+        image.add_section(
+            sect_name(sect),
+            addr_start,
+            u64::from(sect.virtual_address),
+            u64::from(size),
+            addr_limit,
+        )?;
+    }
+    let text = image.text()?;
+    let wanted = |idx: usize| idx == text || opts.data;
+    let is_code =
+        |idx: usize| pe.sections[idx].characteristics & IMAGE_SCN_CNT_CODE != 0;
+
+    let coff = pe.header.coff_header;
+    let symtab = match coff.pointer_to_symbol_table {
+        0 => None,
+        _ => coff.symbols(&map).ok().zip(coff.strings(&map).ok()),
+    };
+    let mut passed = 0;
+    for (_, inline, sym) in symtab.iter().flat_map(|(syms, _)| syms.iter()) {
+        let idx = match usize::try_from(sym.section_number) {
+            // Section numbers are 1-based, with 0 and below reserved
+            Ok(num) if num >= 1 && num <= image.sections.len() => num - 1,
+            _ => continue,
+        };
+        if !wanted(idx) {
+            continue;
+        }
+        // Toolchains often leave the COFF type unset, so treat anything
+        // located in a code section as a function.
+        let is_func =
+            sym.derived_type() == IMAGE_SYM_DTYPE_FUNCTION || is_code(idx);
+        match sym.storage_class {
+            IMAGE_SYM_CLASS_EXTERNAL => {}
+            IMAGE_SYM_CLASS_STATIC
+                if !sym.is_section_definition() && (is_func || opts.locals) => {
+            }
+            _ => continue,
+        }
+        passed += 1;
+        let name = match inline {
+            Some(name) => name,
+            None => match symtab.as_ref().map(|(_, strs)| sym.name(strs)) {
+                Some(Ok(name)) => name,
+                _ => continue,
+            },
+        };
+        if is_noise(name) {
+            continue;
+        }
+        // A corrupt value may take the symbol past the address space
+        let start = image.sections[idx].start;
+        let addr = match start.checked_add(u64::from(sym.value)) {
+            Some(addr) => addr,
+            None => continue,
+        };
+        image.add_sym(idx, addr, name, is_func, opts);
+    }
+    if opts.verbose >= 1 {
+        eprintln!(
+            "{}: {} COFF symbols passed the section and class filters",
+            path.display(),
+            passed
+        );
+    }
+
+    for exp in pe.exports.iter().filter(|exp| exp.reexport.is_none()) {
+        let (name, addr) =
+            match (exp.name, addr_start.checked_add(exp.rva as u64)) {
+                (Some(name), Some(addr)) => (name, addr),
+                _ => continue,
+            };
+        let sections = &image.sections;
+        match sections.iter().position(|s| s.start <= addr && addr < s.end) {
+            Some(idx) if wanted(idx) => {
+                image.add_sym(idx, addr, name, is_code(idx), opts)
+            }
+            _ => {}
+        }
+    }
+    image.finish()
+}
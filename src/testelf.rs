@@ -0,0 +1,299 @@
+//! A builder of small little-endian ELF objects, and scratch directories to
+//! put them in, for the tests.  Only what `process_file` reads is written:
+//! the section headers, with a `.symtab`, `.strtab` and `.shstrtab` appended
+//! to those added.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use goblin::elf::header::{EM_X86_64, ET_DYN};
+use goblin::elf::section_header::{
+    SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_PROGBITS, SHT_STRTAB, SHT_SYMTAB,
+};
+use goblin::elf::sym::{STB_GLOBAL, STT_FUNC, STT_OBJECT};
+
+/// A section to be written
+pub struct Sect {
+    pub name: String,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub data: Vec<u8>,
+    /// The `sh_size`, when other than the length of `data`, as for a large
+    /// section whose contents are of no interest
+    pub size: Option<u64>,
+}
+
+/// A symbol to be written
+pub struct Sym {
+    pub name: String,
+    pub shndx: u16,
+    pub value: u64,
+    pub size: u64,
+    pub bind: u8,
+    pub typ: u8,
+    /// The `st_name`, when other than the offset of `name` in `.strtab`
+    pub st_name: Option<u32>,
+}
+
+pub struct ElfBuilder {
+    pub is_64: bool,
+    pub machine: u16,
+    pub e_type: u16,
+    sects: Vec<Sect>,
+    syms: Vec<Sym>,
+}
+
+impl ElfBuilder {
+    /// An x86-64 shared object, as EDK2 builds its modules
+    pub fn new() -> Self {
+        ElfBuilder {
+            is_64: true,
+            machine: EM_X86_64,
+            e_type: ET_DYN,
+            sects: Vec::new(),
+            syms: Vec::new(),
+        }
+    }
+
+    /// Add `sect`, returning its index
+    pub fn add_section(&mut self, sect: Sect) -> u16 {
+        self.sects.push(sect);
+        self.sects.len() as u16
+    }
+
+    /// Add a code section of `size` bytes at `addr`
+    pub fn code(&mut self, name: &str, addr: u64, size: u64) -> u16 {
+        let flags = u64::from(SHF_ALLOC | SHF_EXECINSTR);
+        self.section(name, flags, addr, size)
+    }
+
+    /// Add a writable data section of `size` bytes at `addr`
+    pub fn data(&mut self, name: &str, addr: u64, size: u64) -> u16 {
+        self.section(name, u64::from(SHF_ALLOC | SHF_WRITE), addr, size)
+    }
+
+    /// Add a section of `size` bytes at `addr`, with `flags`
+    pub fn section(
+        &mut self,
+        name: &str,
+        flags: u64,
+        addr: u64,
+        size: u64,
+    ) -> u16 {
+        self.add_section(Sect {
+            name: name.to_string(),
+            sh_type: SHT_PROGBITS,
+            flags,
+            addr,
+            data: Vec::new(),
+            size: Some(size),
+        })
+    }
+
+    pub fn add_sym(&mut self, sym: Sym) {
+        self.syms.push(sym);
+    }
+
+    /// Add a symbol in section `shndx` (or `SHN_ABS`)
+    pub fn sym(
+        &mut self,
+        shndx: u16,
+        name: &str,
+        value: u64,
+        size: u64,
+        bind: u8,
+        typ: u8,
+    ) {
+        self.add_sym(Sym {
+            name: name.to_string(),
+            shndx,
+            value,
+            size,
+            bind,
+            typ,
+            st_name: None,
+        });
+    }
+
+    /// Add a global function
+    pub fn func(&mut self, shndx: u16, name: &str, value: u64, size: u64) {
+        self.sym(shndx, name, value, size, STB_GLOBAL, STT_FUNC);
+    }
+
+    /// Add a global object
+    pub fn object(&mut self, shndx: u16, name: &str, value: u64, size: u64) {
+        self.sym(shndx, name, value, size, STB_GLOBAL, STT_OBJECT);
+    }
+
+    /// The bytes of the object
+    pub fn build(&self) -> Vec<u8> {
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; if self.is_64 { 24 } else { 16 }];
+        for sym in self.syms.iter() {
+            let st_name = sym.st_name.unwrap_or(strtab.len() as u32);
+            strtab.extend_from_slice(sym.name.as_bytes());
+            strtab.push(0);
+            let info = sym.bind << 4 | sym.typ;
+            let mut ent = Vec::new();
+            put(&mut ent, 4, st_name.into());
+            if self.is_64 {
+                ent.extend_from_slice(&[info, 0]);
+                put(&mut ent, 2, sym.shndx.into());
+                put(&mut ent, 8, sym.value);
+                put(&mut ent, 8, sym.size);
+            } else {
+                put(&mut ent, 4, sym.value);
+                put(&mut ent, 4, sym.size);
+                ent.extend_from_slice(&[info, 0]);
+                put(&mut ent, 2, sym.shndx.into());
+            }
+            symtab.extend_from_slice(&ent);
+        }
+
+        let entsize = if self.is_64 { 24 } else { 16 };
+        let mut hdrs: Vec<Hdr> = self
+            .sects
+            .iter()
+            .map(|s| Hdr {
+                name: &s.name,
+                sh_type: s.sh_type,
+                flags: s.flags,
+                addr: s.addr,
+                data: &s.data,
+                size: s.size.unwrap_or(s.data.len() as u64),
+                link: 0,
+                entsize: 0,
+            })
+            .collect();
+        let strndx = hdrs.len() as u32 + 2;
+        hdrs.push(Hdr::table(".symtab", SHT_SYMTAB, &symtab, strndx, entsize));
+        hdrs.push(Hdr::table(".strtab", SHT_STRTAB, &strtab, 0, 0));
+        let mut shstrtab = vec![0u8];
+        let mut names = Vec::new();
+        for name in hdrs.iter().map(|h| h.name).chain([".shstrtab"]) {
+            names.push(shstrtab.len() as u64);
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+        }
+        hdrs.push(Hdr::table(".shstrtab", SHT_STRTAB, &shstrtab, 0, 0));
+
+        let (ehsize, shentsize) = if self.is_64 { (64, 64) } else { (52, 40) };
+        let mut out = vec![0u8; ehsize];
+        let mut offsets = Vec::new();
+        for hdr in hdrs.iter() {
+            offsets.push(if hdr.data.is_empty() { 0 } else { out.len() });
+            out.extend_from_slice(hdr.data);
+        }
+        while out.len() % 8 != 0 {
+            out.push(0);
+        }
+        let shoff = out.len() as u64;
+        let wide = if self.is_64 { 8 } else { 4 };
+        out.extend_from_slice(&vec![0u8; shentsize]);
+        for (idx, hdr) in hdrs.iter().enumerate() {
+            put(&mut out, 4, names[idx]);
+            put(&mut out, 4, hdr.sh_type.into());
+            put(&mut out, wide, hdr.flags);
+            put(&mut out, wide, hdr.addr);
+            put(&mut out, wide, offsets[idx] as u64);
+            put(&mut out, wide, hdr.size);
+            put(&mut out, 4, hdr.link.into());
+            put(&mut out, 4, 0);
+            put(&mut out, wide, 1);
+            put(&mut out, wide, hdr.entsize);
+        }
+
+        let mut ehdr = Vec::new();
+        ehdr.extend_from_slice(b"\x7fELF");
+        ehdr.extend_from_slice(&[if self.is_64 { 2 } else { 1 }, 1, 1]);
+        ehdr.extend_from_slice(&[0; 9]);
+        put(&mut ehdr, 2, self.e_type.into());
+        put(&mut ehdr, 2, self.machine.into());
+        put(&mut ehdr, 4, 1);
+        put(&mut ehdr, wide, 0);
+        put(&mut ehdr, wide, 0);
+        put(&mut ehdr, wide, shoff);
+        put(&mut ehdr, 4, 0);
+        put(&mut ehdr, 2, ehsize as u64);
+        put(&mut ehdr, 2, 0);
+        put(&mut ehdr, 2, 0);
+        put(&mut ehdr, 2, shentsize as u64);
+        put(&mut ehdr, 2, hdrs.len() as u64 + 1);
+        put(&mut ehdr, 2, hdrs.len() as u64);
+        out[..ehsize].copy_from_slice(&ehdr);
+        out
+    }
+
+    /// Write the object to `path`
+    pub fn write(&self, path: &Path) {
+        std::fs::write(path, self.build()).unwrap();
+    }
+}
+
+/// A section header, as written
+struct Hdr<'a> {
+    name: &'a str,
+    sh_type: u32,
+    flags: u64,
+    addr: u64,
+    data: &'a [u8],
+    size: u64,
+    link: u32,
+    entsize: u64,
+}
+
+impl<'a> Hdr<'a> {
+    fn table(
+        name: &'a str,
+        sh_type: u32,
+        data: &'a [u8],
+        link: u32,
+        entsize: u64,
+    ) -> Self {
+        let size = data.len() as u64;
+        Hdr { name, sh_type, flags: 0, addr: 0, data, size, link, entsize }
+    }
+}
+
+/// Append the low `len` bytes of `val`, little-endian
+fn put(out: &mut Vec<u8>, len: usize, val: u64) {
+    out.extend_from_slice(&val.to_le_bytes()[..len]);
+}
+
+/// The path of the fixture `name` in the `testdata` directory
+pub fn testdata(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join(name)
+}
+
+/// A directory of its own for a test, removed once done with
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "mdb-edk2-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// The path of `name` within the directory
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
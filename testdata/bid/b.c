@@ -0,0 +1 @@
+int _ModuleEntryPoint(void) { return 0; }
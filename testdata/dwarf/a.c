@@ -0,0 +1,5 @@
+int
+foo(int x)
+{
+	return x + 1;
+}
@@ -0,0 +1,7 @@
+int foo(int);
+
+int
+bar(int x)
+{
+	return foo(x) * 2;
+}
@@ -0,0 +1,3 @@
+int g = 3;
+int foo(int x){return x+g;}
+int bar(void){return foo(2);}
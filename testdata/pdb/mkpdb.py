@@ -0,0 +1,114 @@
+#!/usr/bin/env python3
+"""Write Foo.pdb: an MSF 7.00 file holding only what is needed to place the
+symbols of an image with a .text at RVA 0x1000 and a .data at RVA 0x2000,
+namely a PDB info stream, a DBI stream, its symbol record stream, and the
+image's section headers."""
+
+import struct
+
+BLOCK = 512
+
+
+def pad(data, align):
+    return data + b"\0" * (-len(data) % align)
+
+
+def record(kind, body, name):
+    rec = struct.pack("<H", kind) + body + name + b"\0"
+    # Records are aligned to 4 bytes, though the length excludes the length
+    # field itself
+    rec = pad(rec + b"\0\0", 4)[:-2]
+    return struct.pack("<H", len(rec)) + rec
+
+
+def pub(flags, seg, off, name):
+    return record(0x110E, struct.pack("<IIH", flags, off, seg), name)
+
+
+def data(kind, seg, off, name):
+    return record(kind, struct.pack("<IIH", 0, off, seg), name)
+
+
+S_UDT, S_LDATA32, S_GDATA32 = 0x1108, 0x110C, 0x110D
+CVPSF_FUNCTION = 0x2
+
+symbols = b"".join([
+    # A user-defined type, shorter than the records read, but not to stop
+    # the reading of those following
+    record(S_UDT, struct.pack("<I", 0x74), b"U8"),
+    pub(CVPSF_FUNCTION, 1, 0x10, b"_ModuleEntryPoint"),
+    pub(CVPSF_FUNCTION, 1, 0x10, b"EfiMain"),
+    pub(CVPSF_FUNCTION, 1, 0x40, b"InternalHelper"),
+    pub(0, 2, 0x8, b"gCounter"),
+    data(S_GDATA32, 2, 0x10, b"gTable"),
+    data(S_LDATA32, 2, 0x20, b"mLocal"),
+])
+
+
+def section(name, rva, size, chars):
+    return struct.pack("<8sIIIIIIHHI", name, size, rva, size, 0, 0, 0, 0, 0,
+                       chars)
+
+
+sections = (section(b".text", 0x1000, 0x100, 0x60000020)
+            + section(b".data", 0x2000, 0x40, 0xC0000040))
+
+SYM_STREAM, SHDR_STREAM = 5, 6
+dbg_header = [0xFFFF] * 11
+dbg_header[5] = SHDR_STREAM
+dbg_header = struct.pack("<11H", *dbg_header)
+# A single module, with no symbols or source files of its own, as readers
+# expect at least one
+module = struct.pack("<I28sHHIIIHHIII", 0, bytes(28), 0, 0xFFFF, 0, 0, 0, 0,
+                     0, 0, 0, 0)
+module = pad(module + b"foo.obj\0foo.obj\0", 4)
+# Module and file counts, one module's first file and file count
+file_info = struct.pack("<HHHH", 1, 0, 0, 0)
+dbi = struct.pack(
+    "<iIIHHHHHHiiiiiIiiHHI",
+    -1, 19990903, 1,        # signature, V70, age
+    0xFFFF, 0x8E00, 0xFFFF,  # globals, build number, publics
+    0, SYM_STREAM, 0,        # DLL version, symbol records, DLL rebuild
+    len(module), 0, 0,       # module info, section contributions and map
+    len(file_info), 0,       # source files, type server map
+    0, len(dbg_header), 0,   # MFC type server, debug header, EC
+    0, 0x8664, 0)            # flags, AMD64, padding
+dbi += module + file_info + dbg_header
+
+info = struct.pack("<III16s", 20000404, 0, 1, bytes(16))
+# An empty named stream map: no strings, and a hash table with no entries
+info += struct.pack("<IIIII", 0, 0, 1, 0, 0)
+
+# Type streams with no records
+tpi = struct.pack("<IIIIIHHIIiIiIiI", 20040203, 56, 0x1000, 0x1000, 0, 0xFFFF,
+                  0xFFFF, 4, 0x3FFFF, 0, 0, 0, 0, 0, 0)
+
+streams = [b"", info, tpi, dbi, tpi, symbols, sections]
+
+# Block 0 holds the superblock, 1 and 2 the free block maps, 3 the block
+# map, and 4 the stream directory, which the streams follow
+stream_blocks = []
+nblocks = 5
+for stream in streams:
+    count = -(-len(stream) // BLOCK)
+    stream_blocks.append(list(range(nblocks, nblocks + count)))
+    nblocks += count
+
+directory = struct.pack("<I", len(streams))
+directory += b"".join(struct.pack("<I", len(s)) for s in streams)
+directory += b"".join(
+    struct.pack("<I", num) for nums in stream_blocks for num in nums)
+assert len(directory) <= BLOCK
+
+superblock = b"Microsoft C/C++ MSF 7.00\r\n\x1aDS\0\0\0"
+superblock += struct.pack("<IIIIII", BLOCK, 1, nblocks, len(directory), 0, 3)
+# In the free block maps, a set bit marks a free block, of which there are
+# none
+out = pad(superblock, BLOCK) + bytes(BLOCK) + bytes(BLOCK)
+out += pad(struct.pack("<I", 4), BLOCK)
+out += pad(directory, BLOCK)
+out += b"".join(pad(stream, BLOCK) for stream in streams)
+assert len(out) == nblocks * BLOCK
+
+with open("Foo.pdb", "wb") as f:
+    f.write(out)
@@ -0,0 +1,14 @@
+    .text
+    .globl _ModuleEntryPoint
+_ModuleEntryPoint:
+    call helper
+    ret
+    .globl helper
+helper:
+    nop
+    nop
+    ret
+    .data
+    .globl gCounter
+gCounter:
+    .long 5